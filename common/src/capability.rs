@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+use strum::VariantNames;
+use strum_macros::{Display, EnumString, VariantNames};
+
+/// Optional features that a client can announce support for
+///
+/// The server only sends the events of a capability to clients that announced it.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    Hash,
+    Display,
+    EnumString,
+    VariantNames,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum Capability {
+    /// Typing indicators of other users
+    Typing,
+}
+
+impl Capability {
+    /// Returns the names of all capabilities, as they are written in `/cap`
+    pub fn names() -> &'static [&'static str] {
+        Self::VARIANTS
+    }
+}
@@ -1,63 +1,633 @@
-use std::fmt;
+use std::{fmt, time::Duration};
 
-use crate::{RoomName, Username};
+use strum_macros::IntoStaticStr;
 
-#[derive(Debug)]
+use crate::{
+    command_spec::{ArgKind, ArgSpec, CommandParseError, CommandSpec, Permission},
+    shell::{quote_text, quote_word, Lexer},
+    Capability, MessageId, RoomName, SearchQuery, Username,
+};
+
+/// A command sent by a client, described by its entry in the registry of commands
+#[derive(Debug, Clone, PartialEq, IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
 pub enum Command {
-    Help,
+    Help(Option<String>),
+    /// Asks for the ways to finish a partial input line
+    Complete(String),
+    #[strum(serialize = "name")]
     ChangeUsername(Username),
+    /// Takes an admin name with the admin secret
+    Login(Username, String),
+    #[strum(serialize = "rooms")]
     ListRooms,
-    Join(RoomName),
+    MarkRead(RoomName, Option<MessageId>),
+    Join(RoomName, Option<String>),
+    Part(RoomName),
+    Focus(RoomName),
+    #[strum(serialize = "msg")]
+    SendMessage(RoomName, String),
+    Edit(MessageId, String),
+    Delete(MessageId),
+    Reply(MessageId, String),
+    Thread(MessageId),
+    Search(SearchQuery),
+    React(MessageId, String),
+    Unreact(MessageId, String),
+    Typing,
+    #[strum(serialize = "cap")]
+    Capability(Capability),
+    Away(Option<String>),
+    #[strum(serialize = "dnd")]
+    DoNotDisturb(Option<String>),
+    Back,
+    Highlight(String),
+    Unhighlight(String),
+    #[strum(serialize = "highlights")]
+    ListHighlights,
+    #[strum(serialize = "users")]
     ListUsers,
+    #[strum(serialize = "file")]
     SendFile(String, String),
     Nudge(Username),
+    #[strum(serialize = "nudges")]
+    AllowNudges(bool),
+    /// Shows the topic without text, or sets it, where an empty text clears it
+    Topic(Option<String>),
+    Welcome(Option<String>),
+    RoomInfo,
+    Op(Username),
+    Deop(Username),
+    Kick(Username, Option<String>),
+    Ban(Username, Option<Duration>),
+    Unban(Username),
+    Mute(Username, Option<Duration>),
+    Unmute(Username),
+    Invite(Username),
+    InviteOnly(bool),
+    Key(Option<String>),
+    Persist(bool),
     Quit,
 }
 
+impl Command {
+    /// Returns the name of the command, without the leading slash
+    pub fn name(&self) -> &'static str {
+        self.into()
+    }
+
+    /// Returns the entry of the command in the registry
+    pub fn spec(&self) -> &'static CommandSpec {
+        CommandSpec::find(self.name()).expect("every command is in the registry")
+    }
+}
+
 impl fmt::Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Command::Help => write!(f, "/help"),
-            Command::ChangeUsername(name) => write!(f, "/name {}", name),
+            Command::Help(None) => write!(f, "/help"),
+            Command::Help(Some(command)) => write!(f, "/help {}", word(command)),
+            Command::Complete(line) => write!(f, "/complete {}", text(line)),
+            Command::ChangeUsername(name) => write!(f, "/name {}", word(name)),
+            Command::Login(name, secret) => write!(f, "/login {} {}", word(name), word(secret)),
             Command::ListRooms => write!(f, "/rooms"),
-            Command::Join(room) => write!(f, "/join {}", room),
+            Command::MarkRead(room, None) => write!(f, "/markread {}", room),
+            Command::MarkRead(room, Some(id)) => write!(f, "/markread {} {}", room, id),
+            Command::Join(room, None) => write!(f, "/join {}", room),
+            Command::Join(room, Some(key)) => write!(f, "/join {} {}", room, word(key)),
+            Command::Part(room) => write!(f, "/part {}", room),
+            Command::Focus(room) => write!(f, "/focus {}", room),
+            Command::SendMessage(room, message) => write!(f, "/msg {} {}", room, text(message)),
+            Command::Edit(id, message) => write!(f, "/edit {} {}", id, text(message)),
+            Command::Delete(id) => write!(f, "/delete {}", id),
+            Command::Reply(id, message) => write!(f, "/reply {} {}", id, text(message)),
+            Command::Thread(id) => write!(f, "/thread {}", id),
+            Command::Search(query) => write!(f, "/search {}", text(query)),
+            Command::React(id, emoji) => write!(f, "/react {} {}", id, word(emoji)),
+            Command::Unreact(id, emoji) => write!(f, "/unreact {} {}", id, word(emoji)),
+            Command::Typing => write!(f, "/typing"),
+            Command::Capability(capability) => write!(f, "/cap {}", capability),
+            Command::Away(None) => write!(f, "/away"),
+            Command::Away(Some(message)) => write!(f, "/away {}", text(message)),
+            Command::DoNotDisturb(None) => write!(f, "/dnd"),
+            Command::DoNotDisturb(Some(message)) => write!(f, "/dnd {}", text(message)),
+            Command::Back => write!(f, "/back"),
+            Command::Highlight(keyword) => write!(f, "/highlight {}", word(keyword)),
+            Command::Unhighlight(keyword) => write!(f, "/unhighlight {}", word(keyword)),
+            Command::ListHighlights => write!(f, "/highlights"),
             Command::ListUsers => write!(f, "/users"),
             Command::SendFile(filename, encoded) => {
-                write!(f, "/file {} {}", filename, encoded)
+                write!(f, "/file {} {}", word(filename), word(encoded))
             }
-            Command::Nudge(username) => write!(f, "/nudge {}", username),
+            Command::Nudge(username) => write!(f, "/nudge {}", word(username)),
+            Command::AllowNudges(enabled) => write!(f, "/nudges {}", on_off(*enabled)),
+            Command::Topic(None) => write!(f, "/topic"),
+            Command::Topic(Some(topic)) => write!(f, "/topic {}", text(topic)),
+            Command::Welcome(None) => write!(f, "/welcome"),
+            Command::Welcome(Some(welcome)) => write!(f, "/welcome {}", text(welcome)),
+            Command::RoomInfo => write!(f, "/roominfo"),
+            Command::Op(username) => write!(f, "/op {}", word(username)),
+            Command::Deop(username) => write!(f, "/deop {}", word(username)),
+            Command::Kick(username, None) => write!(f, "/kick {}", word(username)),
+            Command::Kick(username, Some(reason)) => {
+                write!(f, "/kick {} {}", word(username), text(reason))
+            }
+            Command::Ban(username, None) => write!(f, "/ban {}", word(username)),
+            Command::Ban(username, Some(duration)) => {
+                write!(
+                    f,
+                    "/ban {} {}",
+                    word(username),
+                    word(humantime::format_duration(*duration))
+                )
+            }
+            Command::Unban(username) => write!(f, "/unban {}", word(username)),
+            Command::Mute(username, None) => write!(f, "/mute {}", word(username)),
+            Command::Mute(username, Some(duration)) => {
+                write!(
+                    f,
+                    "/mute {} {}",
+                    word(username),
+                    word(humantime::format_duration(*duration))
+                )
+            }
+            Command::Unmute(username) => write!(f, "/unmute {}", word(username)),
+            Command::Invite(username) => write!(f, "/invite {}", word(username)),
+            Command::InviteOnly(enabled) => write!(f, "/inviteonly {}", on_off(*enabled)),
+            Command::Key(None) => write!(f, "/key"),
+            Command::Key(Some(key)) => write!(f, "/key {}", word(key)),
+            Command::Persist(enabled) => write!(f, "/persist {}", on_off(*enabled)),
             Command::Quit => write!(f, "/quit"),
         }
     }
 }
 
 impl TryFrom<String> for Command {
-    type Error = String;
+    type Error = CommandParseError;
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let mut parts = value.split_whitespace();
-        match parts.next() {
-            Some("/help") => Ok(Command::Help),
-            Some("/name") => {
-                let name = parts.next().ok_or("Name is required")?.into();
-                Ok(Command::ChangeUsername(name))
-            }
-            Some("/rooms") => Ok(Command::ListRooms),
-            Some("/join" | "/j") => {
-                let room = parts.next().ok_or("Room name is required")?.into();
-                Ok(Command::Join(room))
-            }
-            Some("/users") => Ok(Command::ListUsers),
-            Some("/file") => {
-                let filename = parts.next().ok_or("File name is required")?.to_string();
-                let encoded = parts.next().ok_or("File content is required")?.to_string();
-                Ok(Command::SendFile(filename, encoded))
-            }
-            Some("/nudge") => {
-                let username = parts.next().ok_or("Username is required")?.into();
-                Ok(Command::Nudge(username))
-            }
-            Some("/quit") => Ok(Command::Quit),
-            _ => Err(format!("Invalid command: {}", value)),
-        }
+        let mut lexer = Lexer::new(&value);
+        let name = lexer.next_word()?.unwrap_or_default();
+        let spec = name
+            .strip_prefix('/')
+            .and_then(CommandSpec::find)
+            .ok_or_else(|| CommandParseError::UnknownCommand(name.clone()))?;
+        spec.parse(&mut lexer)
+    }
+}
+
+/// The registry of all commands, in the order they are listed in the help
+pub(crate) static COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "help",
+        aliases: &[],
+        args: &[ArgSpec::optional("command", ArgKind::Command)],
+        permission: Permission::Anyone,
+        help: "List all commands, or show the details of one",
+        examples: &["/help", "/help join"],
+        parse: |args| Ok(Command::Help(args.opt_text(0))),
+    },
+    CommandSpec {
+        name: "complete",
+        aliases: &[],
+        args: &[ArgSpec::required("line", ArgKind::Text)],
+        permission: Permission::Anyone,
+        help: "Suggest how to finish a partial input line, quoted to keep trailing spaces",
+        examples: &["/complete /join ru", "/complete \"/nudge \""],
+        parse: |args| Ok(Command::Complete(args.text(0))),
+    },
+    CommandSpec {
+        name: "name",
+        aliases: &[],
+        args: &[ArgSpec::required("name", ArgKind::User)],
+        permission: Permission::Anyone,
+        help: "Change your name",
+        examples: &["/name alice"],
+        parse: |args| Ok(Command::ChangeUsername(args.new_user(0)?)),
+    },
+    CommandSpec {
+        name: "login",
+        aliases: &[],
+        args: &[
+            ArgSpec::required("name", ArgKind::Word),
+            ArgSpec::required("secret", ArgKind::Word),
+        ],
+        permission: Permission::Anyone,
+        help: "Take an admin name, which needs the secret of the admins",
+        examples: &["/login alice hunter2"],
+        parse: |args| Ok(Command::Login(args.user(0), args.text(1))),
+    },
+    CommandSpec {
+        name: "rooms",
+        aliases: &[],
+        args: &[],
+        permission: Permission::Anyone,
+        help: "List the rooms with their unread messages",
+        examples: &["/rooms"],
+        parse: |_| Ok(Command::ListRooms),
+    },
+    CommandSpec {
+        name: "join",
+        aliases: &["j"],
+        args: &[
+            ArgSpec::required("room", ArgKind::Room),
+            ArgSpec::optional("key", ArgKind::Word),
+        ],
+        permission: Permission::Anyone,
+        help: "Join a room, creating it if needed, or focus it if you are already in it",
+        examples: &["/join rust", "/j secret hunter2"],
+        parse: |args| Ok(Command::Join(args.room(0)?, args.opt_text(1))),
+    },
+    CommandSpec {
+        name: "part",
+        aliases: &[],
+        args: &[ArgSpec::required("room", ArgKind::Room)],
+        permission: Permission::Anyone,
+        help: "Leave a room",
+        examples: &["/part rust"],
+        parse: |args| Ok(Command::Part(args.room(0)?)),
+    },
+    CommandSpec {
+        name: "focus",
+        aliases: &[],
+        args: &[ArgSpec::required("room", ArgKind::Room)],
+        permission: Permission::Anyone,
+        help: "Send your plain messages to another room you are in",
+        examples: &["/focus lobby"],
+        parse: |args| Ok(Command::Focus(args.room(0)?)),
+    },
+    CommandSpec {
+        name: "msg",
+        aliases: &[],
+        args: &[
+            ArgSpec::required("room", ArgKind::Room),
+            ArgSpec::required("message", ArgKind::Text),
+        ],
+        permission: Permission::Anyone,
+        help: "Send a message to a room you are in without focusing it",
+        examples: &["/msg rust anyone around?"],
+        parse: |args| Ok(Command::SendMessage(args.room(0)?, args.text(1))),
+    },
+    CommandSpec {
+        name: "markread",
+        aliases: &[],
+        args: &[
+            ArgSpec::required("room", ArgKind::Room),
+            ArgSpec::optional("id", ArgKind::MessageId),
+        ],
+        permission: Permission::Anyone,
+        help: "Mark the messages of a room as read, up to the given one or all of them",
+        examples: &["/markread rust", "/markread rust 42"],
+        parse: |args| Ok(Command::MarkRead(args.room(0)?, args.opt_message_id(1)?)),
+    },
+    CommandSpec {
+        name: "edit",
+        aliases: &[],
+        args: &[
+            ArgSpec::required("id", ArgKind::MessageId),
+            ArgSpec::required("message", ArgKind::Text),
+        ],
+        permission: Permission::Anyone,
+        help: "Change the text of one of your messages",
+        examples: &["/edit 42 fixed the typo"],
+        parse: |args| Ok(Command::Edit(args.message_id(0)?, args.text(1))),
+    },
+    CommandSpec {
+        name: "delete",
+        aliases: &[],
+        args: &[ArgSpec::required("id", ArgKind::MessageId)],
+        permission: Permission::Anyone,
+        help: "Delete one of your messages",
+        examples: &["/delete 42"],
+        parse: |args| Ok(Command::Delete(args.message_id(0)?)),
+    },
+    CommandSpec {
+        name: "reply",
+        aliases: &[],
+        args: &[
+            ArgSpec::required("id", ArgKind::MessageId),
+            ArgSpec::required("message", ArgKind::Text),
+        ],
+        permission: Permission::Anyone,
+        help: "Reply to a message",
+        examples: &["/reply 42 agreed"],
+        parse: |args| Ok(Command::Reply(args.message_id(0)?, args.text(1))),
+    },
+    CommandSpec {
+        name: "thread",
+        aliases: &[],
+        args: &[ArgSpec::required("id", ArgKind::MessageId)],
+        permission: Permission::Anyone,
+        help: "Show the thread that a message belongs to",
+        examples: &["/thread 42"],
+        parse: |args| Ok(Command::Thread(args.message_id(0)?)),
+    },
+    CommandSpec {
+        name: "search",
+        aliases: &[],
+        args: &[ArgSpec::required("query", ArgKind::Text)],
+        permission: Permission::Anyone,
+        help: "Search messages, filtering with in:room, from:name, before:YYYY-MM-DD and page:n",
+        examples: &[
+            "/search deploy",
+            "/search release in:rust from:alice before:2024-12-31 page:2",
+        ],
+        parse: |args| Ok(Command::Search(args.search_query(0)?)),
+    },
+    CommandSpec {
+        name: "react",
+        aliases: &[],
+        args: &[
+            ArgSpec::required("id", ArgKind::MessageId),
+            ArgSpec::required("emoji", ArgKind::Word),
+        ],
+        permission: Permission::Anyone,
+        help: "React to a message with an emoji",
+        examples: &["/react 42 👍"],
+        parse: |args| Ok(Command::React(args.message_id(0)?, args.text(1))),
+    },
+    CommandSpec {
+        name: "unreact",
+        aliases: &[],
+        args: &[
+            ArgSpec::required("id", ArgKind::MessageId),
+            ArgSpec::required("emoji", ArgKind::Word),
+        ],
+        permission: Permission::Anyone,
+        help: "Take back your reaction to a message",
+        examples: &["/unreact 42 👍"],
+        parse: |args| Ok(Command::Unreact(args.message_id(0)?, args.text(1))),
+    },
+    CommandSpec {
+        name: "typing",
+        aliases: &[],
+        args: &[],
+        permission: Permission::Anyone,
+        help: "Tell the focused room that you are typing",
+        examples: &["/typing"],
+        parse: |_| Ok(Command::Typing),
+    },
+    CommandSpec {
+        name: "cap",
+        aliases: &[],
+        args: &[ArgSpec::required("capability", ArgKind::Capability)],
+        permission: Permission::Anyone,
+        help: "Announce that your client supports an optional feature",
+        examples: &["/cap typing"],
+        parse: |args| Ok(Command::Capability(args.capability(0)?)),
+    },
+    CommandSpec {
+        name: "away",
+        aliases: &[],
+        args: &[ArgSpec::optional("message", ArgKind::Text)],
+        permission: Permission::Anyone,
+        help: "Mark yourself as away",
+        examples: &["/away", "/away back after lunch"],
+        parse: |args| Ok(Command::Away(args.opt_text(0))),
+    },
+    CommandSpec {
+        name: "dnd",
+        aliases: &[],
+        args: &[ArgSpec::optional("message", ArgKind::Text)],
+        permission: Permission::Anyone,
+        help: "Ask not to be disturbed by mentions and nudges",
+        examples: &["/dnd", "/dnd in a meeting"],
+        parse: |args| Ok(Command::DoNotDisturb(args.opt_text(0))),
+    },
+    CommandSpec {
+        name: "back",
+        aliases: &[],
+        args: &[],
+        permission: Permission::Anyone,
+        help: "Mark yourself as online again",
+        examples: &["/back"],
+        parse: |_| Ok(Command::Back),
+    },
+    CommandSpec {
+        name: "highlight",
+        aliases: &[],
+        args: &[ArgSpec::required("keyword", ArgKind::Word)],
+        permission: Permission::Anyone,
+        help: "Get notified about messages containing a word",
+        examples: &["/highlight deploy"],
+        parse: |args| Ok(Command::Highlight(args.text(0))),
+    },
+    CommandSpec {
+        name: "unhighlight",
+        aliases: &[],
+        args: &[ArgSpec::required("keyword", ArgKind::Word)],
+        permission: Permission::Anyone,
+        help: "Stop getting notified about a word",
+        examples: &["/unhighlight deploy"],
+        parse: |args| Ok(Command::Unhighlight(args.text(0))),
+    },
+    CommandSpec {
+        name: "highlights",
+        aliases: &[],
+        args: &[],
+        permission: Permission::Anyone,
+        help: "List your highlight keywords",
+        examples: &["/highlights"],
+        parse: |_| Ok(Command::ListHighlights),
+    },
+    CommandSpec {
+        name: "users",
+        aliases: &[],
+        args: &[],
+        permission: Permission::Anyone,
+        help: "List the users of the focused room",
+        examples: &["/users"],
+        parse: |_| Ok(Command::ListUsers),
+    },
+    CommandSpec {
+        name: "file",
+        aliases: &[],
+        args: &[
+            ArgSpec::required("file name", ArgKind::Word),
+            ArgSpec::required("file content", ArgKind::Word),
+        ],
+        permission: Permission::Anyone,
+        help: "Send a base64-encoded file to the focused room",
+        examples: &["/file notes.md SGVsbG8="],
+        parse: |args| Ok(Command::SendFile(args.text(0), args.text(1))),
+    },
+    CommandSpec {
+        name: "nudge",
+        aliases: &[],
+        args: &[ArgSpec::required("name", ArgKind::User)],
+        permission: Permission::Anyone,
+        help: "Nudge a user, wherever they are",
+        examples: &["/nudge alice"],
+        parse: |args| Ok(Command::Nudge(args.user(0))),
+    },
+    CommandSpec {
+        name: "nudges",
+        aliases: &[],
+        args: &[ArgSpec::required("on|off", ArgKind::Toggle)],
+        permission: Permission::Anyone,
+        help: "Allow or refuse nudges from others",
+        examples: &["/nudges off"],
+        parse: |args| Ok(Command::AllowNudges(args.toggle(0)?)),
+    },
+    CommandSpec {
+        name: "topic",
+        aliases: &[],
+        args: &[ArgSpec::optional("text", ArgKind::Text)],
+        permission: Permission::Anyone,
+        help: "Show the topic of the focused room, or set it as an operator (\"\" clears it)",
+        examples: &["/topic", "/topic Release planning", "/topic \"\""],
+        parse: |args| Ok(Command::Topic(args.opt_text(0))),
+    },
+    CommandSpec {
+        name: "welcome",
+        aliases: &[],
+        args: &[ArgSpec::optional("text", ArgKind::Text)],
+        permission: Permission::Operator,
+        help: "Set or clear the message shown to users joining the focused room",
+        examples: &["/welcome Be nice!", "/welcome"],
+        parse: |args| Ok(Command::Welcome(args.opt_text(0))),
+    },
+    CommandSpec {
+        name: "roominfo",
+        aliases: &[],
+        args: &[],
+        permission: Permission::Anyone,
+        help: "Show the details of the focused room",
+        examples: &["/roominfo"],
+        parse: |_| Ok(Command::RoomInfo),
+    },
+    CommandSpec {
+        name: "op",
+        aliases: &[],
+        args: &[ArgSpec::required("name", ArgKind::Member)],
+        permission: Permission::Operator,
+        help: "Make a user an operator of the focused room",
+        examples: &["/op alice"],
+        parse: |args| Ok(Command::Op(args.user(0))),
+    },
+    CommandSpec {
+        name: "deop",
+        aliases: &[],
+        args: &[ArgSpec::required("name", ArgKind::Member)],
+        permission: Permission::Operator,
+        help: "Remove the operator status of a user",
+        examples: &["/deop alice"],
+        parse: |args| Ok(Command::Deop(args.user(0))),
+    },
+    CommandSpec {
+        name: "kick",
+        aliases: &[],
+        args: &[
+            ArgSpec::required("name", ArgKind::Member),
+            ArgSpec::optional("reason", ArgKind::Text),
+        ],
+        permission: Permission::Operator,
+        help: "Remove a user from the focused room",
+        examples: &["/kick bob", "/kick bob stop spamming"],
+        parse: |args| Ok(Command::Kick(args.user(0), args.opt_text(1))),
+    },
+    CommandSpec {
+        name: "ban",
+        aliases: &[],
+        args: &[
+            ArgSpec::required("name", ArgKind::User),
+            ArgSpec::optional("duration", ArgKind::Duration),
+        ],
+        permission: Permission::Operator,
+        help: "Remove a user from the focused room and keep them out, for a while or for good",
+        examples: &["/ban bob", "/ban bob 1h"],
+        parse: |args| Ok(Command::Ban(args.user(0), args.opt_duration(1)?)),
+    },
+    CommandSpec {
+        name: "unban",
+        aliases: &[],
+        args: &[ArgSpec::required("name", ArgKind::User)],
+        permission: Permission::Operator,
+        help: "Let a banned user join the focused room again",
+        examples: &["/unban bob"],
+        parse: |args| Ok(Command::Unban(args.user(0))),
+    },
+    CommandSpec {
+        name: "mute",
+        aliases: &[],
+        args: &[
+            ArgSpec::required("name", ArgKind::Member),
+            ArgSpec::optional("duration", ArgKind::Duration),
+        ],
+        permission: Permission::Operator,
+        help: "Keep a user from sending messages to the focused room",
+        examples: &["/mute bob", "/mute bob 10m"],
+        parse: |args| Ok(Command::Mute(args.user(0), args.opt_duration(1)?)),
+    },
+    CommandSpec {
+        name: "unmute",
+        aliases: &[],
+        args: &[ArgSpec::required("name", ArgKind::User)],
+        permission: Permission::Operator,
+        help: "Let a muted user send messages again",
+        examples: &["/unmute bob"],
+        parse: |args| Ok(Command::Unmute(args.user(0))),
+    },
+    CommandSpec {
+        name: "invite",
+        aliases: &[],
+        args: &[ArgSpec::required("name", ArgKind::User)],
+        permission: Permission::Operator,
+        help: "Invite a user to the focused room",
+        examples: &["/invite alice"],
+        parse: |args| Ok(Command::Invite(args.user(0))),
+    },
+    CommandSpec {
+        name: "inviteonly",
+        aliases: &[],
+        args: &[ArgSpec::required("on|off", ArgKind::Toggle)],
+        permission: Permission::Operator,
+        help: "Only let invited users join the focused room",
+        examples: &["/inviteonly on"],
+        parse: |args| Ok(Command::InviteOnly(args.toggle(0)?)),
+    },
+    CommandSpec {
+        name: "key",
+        aliases: &[],
+        args: &[ArgSpec::optional("key", ArgKind::Word)],
+        permission: Permission::Operator,
+        help: "Set or clear the key needed to join the focused room",
+        examples: &["/key hunter2", "/key"],
+        parse: |args| Ok(Command::Key(args.opt_text(0))),
+    },
+    CommandSpec {
+        name: "persist",
+        aliases: &[],
+        args: &[ArgSpec::required("on|off", ArgKind::Toggle)],
+        permission: Permission::Operator,
+        help: "Keep the focused room when it becomes empty",
+        examples: &["/persist on"],
+        parse: |args| Ok(Command::Persist(args.toggle(0)?)),
+    },
+    CommandSpec {
+        name: "quit",
+        aliases: &[],
+        args: &[],
+        permission: Permission::Anyone,
+        help: "Disconnect from the server",
+        examples: &["/quit"],
+        parse: |_| Ok(Command::Quit),
+    },
+];
+
+/// Formats a single-word argument so that it parses back to the same value
+fn word(value: impl fmt::Display) -> String {
+    quote_word(&value.to_string()).into_owned()
+}
+
+/// Formats a rest-of-line argument so that it parses back to the same value
+fn text(value: impl fmt::Display) -> String {
+    quote_text(&value.to_string()).into_owned()
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        "on"
+    } else {
+        "off"
     }
 }
@@ -0,0 +1,352 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    command::COMMANDS,
+    shell::{unquote_text, Lexer, UnterminatedQuote},
+    Capability, Command, MessageId, RoomName, SearchQuery, Username,
+};
+
+/// Who is allowed to run a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Permission {
+    Anyone,
+    /// Operators of the current room and admins
+    Operator,
+}
+
+/// The type of a command argument, which decides how it is parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArgKind {
+    /// A single word
+    Word,
+    /// The rest of the line, which must be the last argument
+    Text,
+    Room,
+    /// Any connected user
+    User,
+    /// A user in the focused room
+    Member,
+    MessageId,
+    /// A duration such as `10m` or `1h 30m`
+    Duration,
+    /// Either `on` or `off`
+    Toggle,
+    Capability,
+    /// The name of a command, with or without the leading slash
+    Command,
+}
+
+/// An argument in the schema of a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub required: bool,
+}
+
+impl ArgSpec {
+    pub const fn required(name: &'static str, kind: ArgKind) -> Self {
+        Self {
+            name,
+            kind,
+            required: true,
+        }
+    }
+
+    pub const fn optional(name: &'static str, kind: ArgKind) -> Self {
+        Self {
+            name,
+            kind,
+            required: false,
+        }
+    }
+
+    /// Returns the description of the argument that is sent to clients
+    pub fn info(&self) -> ArgInfo {
+        ArgInfo {
+            name: self.name.to_string(),
+            kind: self.kind,
+            required: self.required,
+        }
+    }
+
+    /// Returns the argument as shown in usage strings, e.g. `{room}` or `[key]`
+    pub fn usage(&self) -> String {
+        if self.required {
+            format!("{{{}}}", self.name)
+        } else {
+            format!("[{}]", self.name)
+        }
+    }
+}
+
+/// The declaration of a command: what it is called, what it takes and who may run it
+///
+/// The registry of all commands drives parsing, the help output, completion, the permission
+/// check and execution: the server runs each command with the handler it keeps under the name of
+/// the entry. Adding a command takes a new [`Command`] variant, an entry here and that handler.
+#[derive(Debug)]
+pub struct CommandSpec {
+    /// The name of the command, without the leading slash
+    pub name: &'static str,
+    /// Other names that the command can be run with
+    pub aliases: &'static [&'static str],
+    pub args: &'static [ArgSpec],
+    pub permission: Permission,
+    /// A one-line description of what the command does
+    pub help: &'static str,
+    /// Complete command lines that show how the command is used
+    pub examples: &'static [&'static str],
+    /// Builds the command from its arguments, which have already been checked against the schema
+    pub(crate) parse: fn(&Args) -> Result<Command, CommandParseError>,
+}
+
+impl CommandSpec {
+    /// Returns all commands in the order they are listed in the help
+    pub fn all() -> &'static [CommandSpec] {
+        COMMANDS
+    }
+
+    /// Finds a command by its name or one of its aliases, without the leading slash
+    pub fn find(name: &str) -> Option<&'static CommandSpec> {
+        COMMANDS
+            .iter()
+            .find(|spec| spec.name == name || spec.aliases.contains(&name))
+    }
+
+    /// Returns how the command is written, e.g. `/join {room} [key]`
+    pub fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name);
+        for arg in self.args {
+            usage.push(' ');
+            usage.push_str(&arg.usage());
+        }
+        usage
+    }
+
+    /// Returns the description of the command that is sent to clients
+    pub fn info(&self) -> CommandInfo {
+        CommandInfo {
+            name: self.name.to_string(),
+            aliases: self.aliases.iter().map(|alias| alias.to_string()).collect(),
+            usage: self.usage(),
+            args: self.args.iter().map(ArgSpec::info).collect(),
+            permission: self.permission,
+            description: self.help.to_string(),
+            examples: self
+                .examples
+                .iter()
+                .map(|example| example.to_string())
+                .collect(),
+        }
+    }
+
+    /// Parses the arguments that follow the command name
+    pub(crate) fn parse(&self, lexer: &mut Lexer) -> Result<Command, CommandParseError> {
+        let args = Args::parse(self, lexer)?;
+        (self.parse)(&args)
+    }
+}
+
+/// A command as described to clients, for help popups and inline hints
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CommandInfo {
+    /// The name of the command, without the leading slash
+    pub name: String,
+    pub aliases: Vec<String>,
+    /// How the command is written, e.g. `/join {room} [key]`
+    pub usage: String,
+    pub args: Vec<ArgInfo>,
+    pub permission: Permission,
+    pub description: String,
+    pub examples: Vec<String>,
+}
+
+/// An argument of a command as described to clients
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ArgInfo {
+    pub name: String,
+    pub kind: ArgKind,
+    pub required: bool,
+}
+
+/// The reasons a command line can be refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandParseError {
+    /// The line does not start with the name of a command, which is kept as written
+    UnknownCommand(String),
+    MissingArgument {
+        command: &'static str,
+        argument: &'static str,
+    },
+    TooManyArguments {
+        command: &'static str,
+        max: usize,
+    },
+    InvalidValue {
+        argument: &'static str,
+        reason: String,
+    },
+    UnterminatedQuote,
+}
+
+impl fmt::Display for CommandParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandParseError::UnknownCommand(name) => write!(f, "Unknown command: {name}"),
+            CommandParseError::MissingArgument { command, argument } => {
+                write!(f, "Missing {argument} for /{command}")
+            }
+            CommandParseError::TooManyArguments { command, max: 0 } => {
+                write!(f, "/{command} takes no arguments")
+            }
+            CommandParseError::TooManyArguments { command, max: 1 } => {
+                write!(f, "/{command} takes a single argument")
+            }
+            CommandParseError::TooManyArguments { command, max } => {
+                write!(f, "/{command} takes at most {max} arguments")
+            }
+            CommandParseError::InvalidValue { argument, reason } => {
+                write!(f, "Invalid {argument}: {reason}")
+            }
+            CommandParseError::UnterminatedQuote => write!(f, "Unterminated quote"),
+        }
+    }
+}
+
+impl std::error::Error for CommandParseError {}
+
+impl From<UnterminatedQuote> for CommandParseError {
+    fn from(_: UnterminatedQuote) -> Self {
+        CommandParseError::UnterminatedQuote
+    }
+}
+
+/// The arguments of a command, split according to its schema
+///
+/// The getters for required arguments panic if the argument is optional in the schema, as that
+/// is a mistake in the registry rather than in the user input.
+#[derive(Debug)]
+pub(crate) struct Args {
+    schema: &'static [ArgSpec],
+    values: Vec<Option<String>>,
+}
+
+impl Args {
+    fn parse(spec: &CommandSpec, lexer: &mut Lexer) -> Result<Self, CommandParseError> {
+        let mut values = Vec::with_capacity(spec.args.len());
+        for arg in spec.args {
+            let value = match arg.kind {
+                ArgKind::Text => unquote_text(lexer.rest()),
+                _ => lexer.next_word()?,
+            };
+            if value.is_none() && arg.required {
+                return Err(CommandParseError::MissingArgument {
+                    command: spec.name,
+                    argument: arg.name,
+                });
+            }
+            values.push(value);
+        }
+        if lexer.next_word()?.is_some() {
+            return Err(CommandParseError::TooManyArguments {
+                command: spec.name,
+                max: spec.args.len(),
+            });
+        }
+        Ok(Self {
+            schema: spec.args,
+            values,
+        })
+    }
+
+    /// Returns an optional argument as it was written
+    pub fn opt(&self, index: usize) -> Option<&str> {
+        self.values[index].as_deref()
+    }
+
+    /// Returns a required argument as it was written
+    pub fn get(&self, index: usize) -> &str {
+        self.opt(index)
+            .expect("required arguments are checked when parsing")
+    }
+
+    pub fn text(&self, index: usize) -> String {
+        self.get(index).to_string()
+    }
+
+    pub fn opt_text(&self, index: usize) -> Option<String> {
+        self.opt(index).map(str::to_string)
+    }
+
+    pub fn room(&self, index: usize) -> Result<RoomName, CommandParseError> {
+        self.get(index)
+            .parse()
+            .map_err(|err| self.invalid(index, err))
+    }
+
+    pub fn user(&self, index: usize) -> Username {
+        self.get(index).into()
+    }
+
+    /// Returns a name that the user wants to go by, which must be a single non-empty word
+    pub fn new_user(&self, index: usize) -> Result<Username, CommandParseError> {
+        let name = self.get(index);
+        if name.is_empty() {
+            return Err(self.invalid(index, "it is empty"));
+        }
+        if name.contains(char::is_whitespace) {
+            return Err(self.invalid(index, "it contains whitespace"));
+        }
+        Ok(name.into())
+    }
+
+    pub fn message_id(&self, index: usize) -> Result<MessageId, CommandParseError> {
+        self.get(index)
+            .parse()
+            .map_err(|err| self.invalid(index, err))
+    }
+
+    pub fn opt_message_id(&self, index: usize) -> Result<Option<MessageId>, CommandParseError> {
+        self.opt(index)
+            .map(|id| id.parse().map_err(|err| self.invalid(index, err)))
+            .transpose()
+    }
+
+    pub fn opt_duration(&self, index: usize) -> Result<Option<Duration>, CommandParseError> {
+        self.opt(index)
+            .map(|duration| {
+                humantime::parse_duration(duration).map_err(|err| self.invalid(index, err))
+            })
+            .transpose()
+    }
+
+    pub fn toggle(&self, index: usize) -> Result<bool, CommandParseError> {
+        match self.get(index) {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(self.invalid(index, "expected on or off")),
+        }
+    }
+
+    pub fn capability(&self, index: usize) -> Result<Capability, CommandParseError> {
+        self.get(index)
+            .parse()
+            .map_err(|_| self.invalid(index, "unknown capability"))
+    }
+
+    pub fn search_query(&self, index: usize) -> Result<SearchQuery, CommandParseError> {
+        self.get(index)
+            .parse()
+            .map_err(|err| self.invalid(index, err))
+    }
+
+    fn invalid(&self, index: usize, reason: impl fmt::Display) -> CommandParseError {
+        CommandParseError::InvalidValue {
+            argument: self.schema[index].name,
+            reason: reason.to_string(),
+        }
+    }
+}
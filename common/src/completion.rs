@@ -0,0 +1,206 @@
+//! Working out which word of a partial input line to complete, and with what
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    shell::{quote_word, Lexer},
+    ArgKind, CommandSpec,
+};
+
+/// What the word to complete is expected to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionTarget {
+    /// The name of a command, including the leading slash
+    Command,
+    /// An argument of a command
+    Arg(ArgKind),
+    /// A username in the text of a message, with or without a leading `@`
+    Mention,
+}
+
+/// The word at the end of a partial input line that is to be completed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    /// The byte offset at which the word starts
+    pub start: usize,
+    /// The word as typed so far, with its quotes and escapes resolved
+    pub prefix: String,
+    /// `None` if there is nothing to suggest, e.g. after the last argument of a command
+    pub target: Option<CompletionTarget>,
+}
+
+/// The suggestions for a partial input line, sent in answer to `/complete`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Completions {
+    /// The line that was completed
+    pub line: String,
+    /// The byte offset in the line from which a candidate replaces the rest of it
+    pub start: usize,
+    /// The possible replacements, quoted where needed and sorted
+    pub candidates: Vec<String>,
+}
+
+impl Completion {
+    pub fn new(line: &str) -> Self {
+        if !line.starts_with('/') {
+            return Self::mention(line);
+        }
+        let mut lexer = Lexer::new(line);
+        let mut words = Vec::new();
+        let mut open_quote = false;
+        let mut end = 0;
+        loop {
+            let start = lexer.offset();
+            match lexer.next_word() {
+                Ok(Some(word)) => {
+                    words.push((start, word));
+                    end = lexer.position();
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    // The word being typed is still in quotes, so everything after it belongs to it
+                    let word = line[start..].replace(['\'', '"', '\\'], "");
+                    words.push((start, word));
+                    open_quote = true;
+                    break;
+                }
+            }
+        }
+        // Whitespace after the last word means that the next word has not been started yet
+        if !open_quote && end < line.len() {
+            words.push((line.len(), String::new()));
+        }
+
+        let index = words.len() - 1;
+        let target = if index == 0 {
+            Some(CompletionTarget::Command)
+        } else {
+            let Some(spec) = words[0].1.strip_prefix('/').and_then(CommandSpec::find) else {
+                return Self::nothing(line);
+            };
+            let text = spec.args.iter().position(|arg| arg.kind == ArgKind::Text);
+            if text.is_some_and(|text| index > text) {
+                return Self::mention(line);
+            }
+            spec.args
+                .get(index - 1)
+                .map(|arg| CompletionTarget::Arg(arg.kind))
+        };
+        let (start, prefix) = words.swap_remove(index);
+        Self {
+            start,
+            prefix,
+            target,
+        }
+    }
+
+    /// Completes the last word of a line of text with the name of a user
+    fn mention(line: &str) -> Self {
+        let word = line.rsplit(char::is_whitespace).next().unwrap_or_default();
+        Self {
+            start: line.len() - word.len(),
+            prefix: word.to_string(),
+            target: Some(CompletionTarget::Mention),
+        }
+    }
+
+    fn nothing(line: &str) -> Self {
+        Self {
+            start: line.len(),
+            prefix: String::new(),
+            target: None,
+        }
+    }
+
+    /// Returns the options that start with the word to complete, ignoring case
+    ///
+    /// The candidates are written the way they have to be typed, so arguments are quoted if
+    /// needed and mentions keep their `@`.
+    pub fn candidates<I>(&self, options: I) -> Vec<String>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let (marker, prefix) = match self.prefix.strip_prefix('@') {
+            Some(prefix) if self.target == Some(CompletionTarget::Mention) => ("@", prefix),
+            _ => ("", self.prefix.as_str()),
+        };
+        let prefix = prefix.to_lowercase();
+        let mut candidates: Vec<String> = options
+            .into_iter()
+            .filter(|option| option.as_ref().to_lowercase().starts_with(&prefix))
+            .map(|option| match self.target {
+                Some(CompletionTarget::Arg(_)) => quote_word(option.as_ref()).into_owned(),
+                _ => format!("{marker}{}", option.as_ref()),
+            })
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completion(start: usize, prefix: &str, target: Option<CompletionTarget>) -> Completion {
+        Completion {
+            start,
+            prefix: prefix.to_string(),
+            target,
+        }
+    }
+
+    #[test]
+    fn command_names_are_completed_from_the_slash() {
+        let target = Some(CompletionTarget::Command);
+        assert_eq!(Completion::new("/"), completion(0, "/", target));
+        assert_eq!(Completion::new("/jo"), completion(0, "/jo", target));
+        let candidates = Completion::new("/JO").candidates(["/join", "/kick", "/j"]);
+        assert_eq!(candidates, ["/join"]);
+    }
+
+    #[test]
+    fn arguments_are_completed_by_their_kind() {
+        let room = Some(CompletionTarget::Arg(ArgKind::Room));
+        let member = Some(CompletionTarget::Arg(ArgKind::Member));
+        assert_eq!(Completion::new("/join "), completion(6, "", room));
+        assert_eq!(Completion::new("/j  lob"), completion(4, "lob", room));
+        assert_eq!(Completion::new("/kick al"), completion(6, "al", member));
+        assert_eq!(
+            Completion::new("/join lobby "),
+            completion(12, "", Some(CompletionTarget::Arg(ArgKind::Word)))
+        );
+        // Past the last argument and after unknown commands, there is nothing to suggest
+        assert_eq!(Completion::new("/part lobby x"), completion(12, "x", None));
+        assert_eq!(Completion::new("/nope x"), completion(7, "", None));
+    }
+
+    #[test]
+    fn words_in_open_quotes_are_completed_whole() {
+        let member = Some(CompletionTarget::Arg(ArgKind::Member));
+        assert_eq!(
+            Completion::new("/kick \"al ic"),
+            completion(6, "al ic", member)
+        );
+        assert_eq!(Completion::new("/kick 'al"), completion(6, "al", member));
+        let completion = Completion::new("/kick \"al ic");
+        assert_eq!(
+            completion.candidates(["al ice", "bob"]),
+            [quote_word("al ice").into_owned()]
+        );
+    }
+
+    #[test]
+    fn text_is_completed_with_mentions() {
+        let mention = Some(CompletionTarget::Mention);
+        assert_eq!(Completion::new("hi @al"), completion(3, "@al", mention));
+        assert_eq!(
+            Completion::new("/msg lobby hi b"),
+            completion(14, "b", mention)
+        );
+        let candidates = Completion::new("hi @AL").candidates(["alice", "bob"]);
+        assert_eq!(candidates, ["@alice"]);
+    }
+}
@@ -1,12 +1,23 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 use strum_macros::Display;
 
-use crate::{RoomName, Username};
+use crate::{
+    Capability, CommandInfo, Completions, Message, MessageId, RoomInfo, RoomName, RoomSummary,
+    SearchResult, UserInfo, Username,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Display)]
 pub enum ServerEvent {
-    #[strum(to_string = "Help({0}, {1})")]
-    CommandHelp(Username, String),
+    #[strum(to_string = "Help({username})")]
+    CommandHelp {
+        username: Username,
+        commands: Vec<CommandInfo>,
+    },
+    /// The details of a single command, in answer to `/help <command>`
+    #[strum(to_string = "CommandInfo({0:?})")]
+    CommandInfo(CommandInfo),
     #[strum(to_string = "{username} {event}")]
     RoomEvent {
         room_name: RoomName,
@@ -14,35 +25,171 @@ pub enum ServerEvent {
         date: String,
         event: RoomEvent,
     },
+    #[strum(to_string = "Completions({0:?})")]
+    Completions(Completions),
     #[strum(to_string = "Room Created({0})")]
     RoomCreated(RoomName),
     #[strum(to_string = "Room Deleted({0})")]
     RoomDeleted(RoomName),
+    #[strum(to_string = "Invite({from} to {room_name})")]
+    Invite { room_name: RoomName, from: Username },
+    #[strum(to_string = "Nudge(from {from})")]
+    Nudge { from: Username },
+    /// Confirms to the sender that their nudge was delivered
+    #[strum(to_string = "NudgeSent(to {to})")]
+    NudgeSent { to: Username },
     #[strum(to_string = "Error({0})")]
     Error(String),
     #[strum(to_string = "Rooms({0:?})")]
-    Rooms(Vec<(RoomName, usize)>),
-    #[strum(to_string = "Users({0:?})")]
-    Users(Vec<Username>),
+    Rooms(Vec<RoomSummary>),
+    #[strum(to_string = "RoomInfo({0:?})")]
+    RoomInfo(RoomInfo),
+    #[strum(to_string = "Users({room_name}, {users:?})")]
+    Users {
+        room_name: RoomName,
+        users: Vec<UserInfo>,
+    },
+    /// A user who shares a room with the receiver changed their presence
+    #[strum(to_string = "Presence({0:?})")]
+    Presence(UserInfo),
+    /// A message that mentions the receiver or contains one of their highlight keywords
+    #[strum(to_string = "Mention({username} in {room_name})")]
+    Mention {
+        room_name: RoomName,
+        username: Username,
+        message: Message,
+        /// The keyword that matched, `None` if the receiver was mentioned by name
+        keyword: Option<String>,
+    },
+    #[strum(to_string = "Highlights({0:?})")]
+    Highlights(Vec<String>),
+    #[strum(to_string = "Focus({0})")]
+    Focus(RoomName),
+    #[strum(to_string = "History({room_name})")]
+    History {
+        room_name: RoomName,
+        events: Vec<ServerEvent>,
+    },
+    #[strum(to_string = "Thread({room_name}, {root})")]
+    Thread {
+        room_name: RoomName,
+        root: MessageId,
+        events: Vec<ServerEvent>,
+    },
+    #[strum(to_string = "SearchResults(page {page} of {pages})")]
+    SearchResults {
+        /// The page of results, starting at 1
+        page: usize,
+        pages: usize,
+        /// How many messages matched on all pages
+        total: usize,
+        /// The matching messages, newest first
+        results: Vec<SearchResult>,
+    },
     #[strum(to_string = "Disconnected")]
     Disconnect,
 }
 
 impl ServerEvent {
-    pub fn help(username: &Username, commands: &str) -> Self {
-        Self::CommandHelp(username.clone(), commands.to_string())
+    pub fn help(username: &Username, commands: Vec<CommandInfo>) -> Self {
+        Self::CommandHelp {
+            username: username.clone(),
+            commands,
+        }
+    }
+
+    pub fn command_info(command: CommandInfo) -> Self {
+        Self::CommandInfo(command)
+    }
+
+    pub fn completions(line: &str, start: usize, candidates: Vec<String>) -> Self {
+        Self::Completions(Completions {
+            line: line.to_string(),
+            start,
+            candidates,
+        })
+    }
+
+    pub fn nudge(from: &Username) -> Self {
+        Self::Nudge { from: from.clone() }
+    }
+
+    pub fn nudge_sent(to: &Username) -> Self {
+        Self::NudgeSent { to: to.clone() }
     }
 
     pub fn error(message: &str) -> Self {
         Self::Error(message.to_string())
     }
 
-    pub fn rooms(rooms: Vec<(RoomName, usize)>) -> Self {
+    pub fn rooms(rooms: Vec<RoomSummary>) -> Self {
         Self::Rooms(rooms)
     }
 
-    pub fn users(users: Vec<Username>) -> Self {
-        Self::Users(users)
+    pub fn room_info(info: RoomInfo) -> Self {
+        Self::RoomInfo(info)
+    }
+
+    pub fn users(room_name: &RoomName, users: Vec<UserInfo>) -> Self {
+        Self::Users {
+            room_name: room_name.clone(),
+            users,
+        }
+    }
+
+    pub fn presence(user: UserInfo) -> Self {
+        Self::Presence(user)
+    }
+
+    pub fn mention(
+        room_name: &RoomName,
+        username: &Username,
+        message: &Message,
+        keyword: Option<&str>,
+    ) -> Self {
+        Self::Mention {
+            room_name: room_name.clone(),
+            username: username.clone(),
+            message: message.clone(),
+            keyword: keyword.map(str::to_string),
+        }
+    }
+
+    pub fn highlights(keywords: Vec<String>) -> Self {
+        Self::Highlights(keywords)
+    }
+
+    pub fn focus(room_name: &RoomName) -> Self {
+        Self::Focus(room_name.clone())
+    }
+
+    pub fn history(room_name: &RoomName, events: Vec<ServerEvent>) -> Self {
+        Self::History {
+            room_name: room_name.clone(),
+            events,
+        }
+    }
+
+    pub fn thread(room_name: &RoomName, root: MessageId, events: Vec<ServerEvent>) -> Self {
+        Self::Thread {
+            room_name: room_name.clone(),
+            root,
+            events,
+        }
+    }
+
+    pub fn search_results(
+        page: usize,
+        pages: usize,
+        total: usize,
+        results: Vec<SearchResult>,
+    ) -> Self {
+        Self::SearchResults {
+            page,
+            pages,
+            total,
+            results,
+        }
     }
 
     pub fn room_event(room_name: &RoomName, username: &Username, event: RoomEvent) -> Self {
@@ -62,6 +209,13 @@ impl ServerEvent {
         Self::RoomDeleted(room_name.clone())
     }
 
+    pub fn invite(room_name: &RoomName, from: &Username) -> Self {
+        Self::Invite {
+            room_name: room_name.clone(),
+            from: from.clone(),
+        }
+    }
+
     pub fn as_json_str(&self) -> String {
         serde_json::to_string(self).unwrap()
     }
@@ -74,7 +228,22 @@ impl ServerEvent {
 #[derive(Debug, Clone, Serialize, Deserialize, Display)]
 pub enum RoomEvent {
     #[strum(to_string = "created room {0}")]
-    Message(String),
+    Message(Message),
+    #[strum(to_string = "edited message {id}")]
+    Edited { id: MessageId, text: String },
+    #[strum(to_string = "deleted message {0}")]
+    Deleted(MessageId),
+    #[strum(to_string = "reacted to message {id} with {emoji}")]
+    Reaction {
+        id: MessageId,
+        emoji: String,
+        /// How many users reacted with this emoji after the change
+        count: usize,
+    },
+    #[strum(to_string = "is typing")]
+    Typing { username: Username },
+    #[strum(to_string = "stopped typing")]
+    TypingStopped { username: Username },
     #[strum(to_string = "sent file: {filename}")]
     File { filename: String, contents: String },
     #[strum(to_string = "joined room {0}")]
@@ -83,13 +252,79 @@ pub enum RoomEvent {
     Left(RoomName),
     #[strum(to_string = "changed name to {0}")]
     NameChange(Username),
-    #[strum(to_string = "nudged {0}")]
-    Nudge(Username),
+    #[strum(to_string = "changed the topic to {0:?}")]
+    TopicChange(Option<String>),
+    #[strum(to_string = "made {0} an operator")]
+    Op(Username),
+    #[strum(to_string = "removed operator status from {0}")]
+    Deop(Username),
+    #[strum(to_string = "kicked {username}")]
+    Kick {
+        username: Username,
+        reason: Option<String>,
+    },
+    #[strum(to_string = "banned {username}")]
+    Ban {
+        username: Username,
+        duration: Option<Duration>,
+    },
+    #[strum(to_string = "unbanned {0}")]
+    Unban(Username),
+    #[strum(to_string = "muted {username}")]
+    Mute {
+        username: Username,
+        duration: Option<Duration>,
+    },
+    #[strum(to_string = "unmuted {0}")]
+    Unmute(Username),
 }
 
 impl RoomEvent {
-    pub fn message(message: &str) -> Self {
-        Self::Message(message.to_string())
+    pub fn message(id: MessageId, message: &str) -> Self {
+        Self::Message(Message::new(id, message))
+    }
+
+    pub fn reply(id: MessageId, message: &str, reply_to: MessageId) -> Self {
+        Self::Message(Message::reply(id, message, reply_to))
+    }
+
+    pub fn edited(id: MessageId, text: &str) -> Self {
+        Self::Edited {
+            id,
+            text: text.to_string(),
+        }
+    }
+
+    pub fn deleted(id: MessageId) -> Self {
+        Self::Deleted(id)
+    }
+
+    pub fn typing(username: &Username) -> Self {
+        Self::Typing {
+            username: username.clone(),
+        }
+    }
+
+    pub fn typing_stopped(username: &Username) -> Self {
+        Self::TypingStopped {
+            username: username.clone(),
+        }
+    }
+
+    /// Returns the capability that a client needs to receive this event, if any
+    pub fn capability(&self) -> Option<Capability> {
+        match self {
+            Self::Typing { .. } | Self::TypingStopped { .. } => Some(Capability::Typing),
+            _ => None,
+        }
+    }
+
+    pub fn reaction(id: MessageId, emoji: &str, count: usize) -> Self {
+        Self::Reaction {
+            id,
+            emoji: emoji.to_string(),
+            count,
+        }
     }
 
     pub fn file(filename: &str, contents: &str) -> Self {
@@ -111,7 +346,44 @@ impl RoomEvent {
         Self::NameChange(username.clone())
     }
 
-    pub fn nudge(username: &Username) -> Self {
-        Self::Nudge(username.clone())
+    pub fn topic_change(topic: Option<&str>) -> Self {
+        Self::TopicChange(topic.map(str::to_string))
+    }
+
+    pub fn op(username: &Username) -> Self {
+        Self::Op(username.clone())
+    }
+
+    pub fn deop(username: &Username) -> Self {
+        Self::Deop(username.clone())
+    }
+
+    pub fn kick(username: &Username, reason: Option<&str>) -> Self {
+        Self::Kick {
+            username: username.clone(),
+            reason: reason.map(str::to_string),
+        }
+    }
+
+    pub fn ban(username: &Username, duration: Option<Duration>) -> Self {
+        Self::Ban {
+            username: username.clone(),
+            duration,
+        }
+    }
+
+    pub fn unban(username: &Username) -> Self {
+        Self::Unban(username.clone())
+    }
+
+    pub fn mute(username: &Username, duration: Option<Duration>) -> Self {
+        Self::Mute {
+            username: username.clone(),
+            duration,
+        }
+    }
+
+    pub fn unmute(username: &Username) -> Self {
+        Self::Unmute(username.clone())
     }
 }
@@ -1,9 +1,27 @@
+pub use capability::Capability;
 pub use command::Command;
+pub use command_spec::{
+    ArgInfo, ArgKind, ArgSpec, CommandInfo, CommandParseError, CommandSpec, Permission,
+};
+pub use completion::{Completion, CompletionTarget, Completions};
 pub use events::{RoomEvent, ServerEvent};
-pub use room_name::RoomName;
+pub use message::{Message, MessageId};
+pub use room_info::{RoomInfo, RoomSummary};
+pub use room_name::{RoomName, RoomNameError};
+pub use search::{SearchQuery, SearchResult};
+pub use shell::split_command;
+pub use user_info::{Presence, UserInfo};
 pub use username::Username;
 
+mod capability;
 mod command;
+mod command_spec;
+mod completion;
 mod events;
+mod message;
+mod room_info;
 mod room_name;
+mod search;
+mod shell;
+mod user_info;
 mod username;
@@ -0,0 +1,90 @@
+use std::{collections::BTreeMap, fmt, num::ParseIntError, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Username;
+
+/// The identifier of a message, unique across all rooms of the server
+#[derive(
+    Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, Default, PartialOrd, Ord,
+)]
+#[serde(transparent)]
+pub struct MessageId(u64);
+
+impl MessageId {
+    pub fn new(value: u64) -> Self {
+        Self(value)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = ParseIntError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+/// A text message sent to a room
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Message {
+    pub id: MessageId,
+    pub text: String,
+    /// Whether the message was changed after it was sent
+    pub edited: bool,
+    /// The message that this one answers
+    pub reply_to: Option<MessageId>,
+    /// How many users reacted with each emoji
+    pub reactions: BTreeMap<String, usize>,
+}
+
+impl Message {
+    pub fn new(id: MessageId, text: &str) -> Self {
+        Self {
+            id,
+            text: text.to_string(),
+            edited: false,
+            reply_to: None,
+            reactions: BTreeMap::new(),
+        }
+    }
+
+    pub fn reply(id: MessageId, text: &str, reply_to: MessageId) -> Self {
+        Self {
+            reply_to: Some(reply_to),
+            ..Self::new(id, text)
+        }
+    }
+
+    /// Returns whether the message mentions the user with `@name`, ignoring case
+    pub fn mentions(&self, username: &Username) -> bool {
+        self.text
+            .split_whitespace()
+            .filter_map(|word| word.strip_prefix('@'))
+            .map(|name| name.trim_end_matches(|c: char| c.is_ascii_punctuation()))
+            .any(|name| name.eq_ignore_ascii_case(username.as_str()))
+    }
+
+    /// Returns whether the message contains the word, ignoring case and surrounding punctuation
+    pub fn contains_word(&self, word: &str) -> bool {
+        self.text
+            .split_whitespace()
+            .map(|w| w.trim_matches(|c: char| c.is_ascii_punctuation()))
+            .any(|w| w.eq_ignore_ascii_case(word))
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
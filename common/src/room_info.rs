@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{RoomName, Username};
+
+/// Detailed information about a room
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RoomInfo {
+    pub name: RoomName,
+    pub topic: Option<String>,
+    /// The user who created the room, `None` for rooms created by the server
+    pub creator: Option<Username>,
+    pub created_at: String,
+    /// The message shown to users when they join the room
+    pub welcome: Option<String>,
+    pub users: usize,
+    /// Whether only invited users can join the room
+    pub invite_only: bool,
+    /// Whether a key is needed to join the room
+    pub has_key: bool,
+    /// Whether the room is kept when it becomes empty
+    pub persistent: bool,
+}
+
+/// A room entry of the room list
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RoomSummary {
+    pub name: RoomName,
+    pub users: usize,
+    pub topic: Option<String>,
+    /// Whether the user who asked for the list is in the room
+    pub joined: bool,
+    /// How many messages of others the user has not read yet, `0` for rooms they are not in
+    pub unread: usize,
+    /// How many of the unread messages mention the user
+    pub mentions: usize,
+}
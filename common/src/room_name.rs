@@ -1,13 +1,35 @@
-use std::{borrow::Cow, convert::Infallible, fmt, str::FromStr};
+use std::{borrow::Cow, fmt, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
+/// The name of a chat room
+///
+/// Room names are normalized to lowercase, so `Dev`, `#dev` and `dev` all refer to the same room.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
 pub struct RoomName(String);
 
 impl RoomName {
-    pub fn new(name: String) -> Self {
-        Self(name)
+    /// The maximum length of a room name in characters
+    pub const MAX_LENGTH: usize = 32;
+
+    /// Validates and normalizes the given room name
+    pub fn new(name: &str) -> Result<Self, RoomNameError> {
+        let name = name.trim();
+        let name = name.strip_prefix('#').unwrap_or(name).to_lowercase();
+        if name.is_empty() {
+            return Err(RoomNameError::Empty);
+        }
+        if name.chars().count() > Self::MAX_LENGTH {
+            return Err(RoomNameError::TooLong);
+        }
+        if let Some(c) = name
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+        {
+            return Err(RoomNameError::InvalidCharacter(c));
+        }
+        Ok(Self(name))
     }
 
     pub fn as_str(&self) -> &str {
@@ -19,28 +41,36 @@ impl RoomName {
     }
 }
 
+impl Default for RoomName {
+    fn default() -> Self {
+        Self::lobby()
+    }
+}
+
 impl fmt::Display for RoomName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
-impl From<String> for RoomName {
-    fn from(value: String) -> Self {
-        Self(value)
+impl TryFrom<String> for RoomName {
+    type Error = RoomNameError;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(&value)
     }
 }
 
-impl From<&str> for RoomName {
-    fn from(value: &str) -> Self {
-        Self(value.to_string())
+impl TryFrom<&str> for RoomName {
+    type Error = RoomNameError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::new(value)
     }
 }
 
 impl FromStr for RoomName {
-    type Err = Infallible;
+    type Err = RoomNameError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(RoomName(s.to_string()))
+        Self::new(s)
     }
 }
 
@@ -61,3 +91,81 @@ impl<'a> From<&'a RoomName> for Cow<'a, str> {
         Cow::Borrowed(&value.0)
     }
 }
+
+/// The reasons a room name can be rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomNameError {
+    Empty,
+    TooLong,
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for RoomNameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RoomNameError::Empty => write!(f, "room name cannot be empty"),
+            RoomNameError::TooLong => write!(
+                f,
+                "room name cannot be longer than {} characters",
+                RoomName::MAX_LENGTH
+            ),
+            RoomNameError::InvalidCharacter(c) => write!(
+                f,
+                "room name cannot contain {c:?}, use letters, digits, '-' or '_'"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RoomNameError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_normalized() {
+        for name in ["dev", "Dev", "#dev", "#DEV", "  dev "] {
+            assert_eq!(RoomName::new(name).unwrap().as_str(), "dev");
+        }
+        assert_eq!("Lobby".parse::<RoomName>(), Ok(RoomName::lobby()));
+        assert_eq!(
+            RoomName::new("Rust_2024-talk").unwrap().as_str(),
+            "rust_2024-talk"
+        );
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        assert_eq!(RoomName::new(""), Err(RoomNameError::Empty));
+        assert_eq!(RoomName::new(" # "), Err(RoomNameError::Empty));
+        assert_eq!(RoomName::new("#"), Err(RoomNameError::Empty));
+        assert_eq!(
+            RoomName::new("two words"),
+            Err(RoomNameError::InvalidCharacter(' '))
+        );
+        assert_eq!(
+            RoomName::new("##dev"),
+            Err(RoomNameError::InvalidCharacter('#'))
+        );
+        assert_eq!(
+            RoomName::new("café"),
+            Err(RoomNameError::InvalidCharacter('é'))
+        );
+
+        let longest = "a".repeat(RoomName::MAX_LENGTH);
+        assert!(RoomName::new(&longest).is_ok());
+        assert!(RoomName::new(&format!("#{longest}")).is_ok());
+        assert_eq!(
+            RoomName::new(&format!("{longest}a")),
+            Err(RoomNameError::TooLong)
+        );
+    }
+
+    #[test]
+    fn deserializing_validates_and_normalizes() {
+        let name: RoomName = serde_json::from_str(r##""#Dev""##).unwrap();
+        assert_eq!(name.as_str(), "dev");
+        assert!(serde_json::from_str::<RoomName>(r#""no way""#).is_err());
+    }
+}
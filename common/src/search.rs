@@ -0,0 +1,124 @@
+use std::{fmt, str::FromStr};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    shell::{quote_word, Lexer},
+    MessageId, RoomName, ServerEvent, Username,
+};
+
+/// A full-text search over the message history of the rooms
+///
+/// Written as `<words> [in:<room>] [from:<user>] [before:<YYYY-MM-DD>] [page:<n>]`, where the
+/// filters can appear anywhere between the words.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// The words that every result contains, in lowercase
+    pub terms: Vec<String>,
+    /// Only search this room
+    pub room: Option<RoomName>,
+    /// Only return messages of this user
+    pub from: Option<Username>,
+    /// Only return messages sent before this day
+    pub before: Option<NaiveDate>,
+    /// The page of results to return, starting at 1
+    pub page: usize,
+}
+
+impl SearchQuery {
+    /// Splits a text into the words that are indexed and searched for
+    ///
+    /// Words are lowercased and stripped of surrounding punctuation.
+    pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+        text.split_whitespace()
+            .map(|word| word.trim_matches(|c: char| c.is_ascii_punctuation()))
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+    }
+}
+
+impl fmt::Display for SearchQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = self.terms.clone();
+        if let Some(room) = &self.room {
+            parts.push(format!("in:{room}"));
+        }
+        if let Some(from) = &self.from {
+            parts.push(format!("from:{}", quote_word(from.as_str())));
+        }
+        if let Some(before) = &self.before {
+            parts.push(format!("before:{}", before.format("%Y-%m-%d")));
+        }
+        if self.page != 1 {
+            parts.push(format!("page:{}", self.page));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl FromStr for SearchQuery {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut query = SearchQuery {
+            terms: Vec::new(),
+            room: None,
+            from: None,
+            before: None,
+            page: 1,
+        };
+        for part in split(s) {
+            let part = part.as_str();
+            if let Some(room) = part.strip_prefix("in:") {
+                let room = room
+                    .parse()
+                    .map_err(|err| format!("Invalid room name: {err}"))?;
+                query.room = Some(room);
+            } else if let Some(from) = part.strip_prefix("from:") {
+                query.from = Some(from.into());
+            } else if let Some(before) = part.strip_prefix("before:") {
+                let before = NaiveDate::parse_from_str(before, "%Y-%m-%d")
+                    .map_err(|err| format!("Invalid date, expected YYYY-MM-DD: {err}"))?;
+                query.before = Some(before);
+            } else if let Some(page) = part.strip_prefix("page:") {
+                query.page = page
+                    .parse()
+                    .ok()
+                    .filter(|page| *page > 0)
+                    .ok_or("Invalid page number")?;
+            } else {
+                query.terms.extend(SearchQuery::words(part));
+            }
+        }
+        let has_filter = query.room.is_some() || query.from.is_some() || query.before.is_some();
+        if query.terms.is_empty() && !has_filter {
+            return Err("Search query is required".to_string());
+        }
+        Ok(query)
+    }
+}
+
+/// Splits a query into its words, like a command line
+///
+/// A query with an unterminated quote is split on whitespace instead, so that a stray quote in
+/// the searched words is not an error.
+fn split(query: &str) -> Vec<String> {
+    let mut lexer = Lexer::new(query);
+    let mut parts = Vec::new();
+    loop {
+        match lexer.next_word() {
+            Ok(Some(part)) => parts.push(part),
+            Ok(None) => return parts,
+            Err(_) => return query.split_whitespace().map(str::to_string).collect(),
+        }
+    }
+}
+
+/// A message that matches a search
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub room_name: RoomName,
+    pub id: MessageId,
+    /// The matching message along with the messages right before and after it, oldest first
+    pub context: Vec<ServerEvent>,
+}
@@ -0,0 +1,178 @@
+//! Shell-style splitting and quoting of command lines
+//!
+//! Words are separated by whitespace. A word can be wrapped in single quotes, which keep
+//! everything between them as is, or in double quotes, inside which `\"`, `\\`, `\n` and `\r` are
+//! escapes. Outside of quotes, a backslash escapes the next character.
+//!
+//! Quoting escapes line breaks, so that any text fits on the single line of the protocol.
+
+use std::borrow::Cow;
+
+use crate::CommandParseError;
+
+/// Splits a command line into words, one at a time
+#[derive(Debug)]
+pub(crate) struct Lexer<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+/// A quote that was opened but never closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UnterminatedQuote;
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self { input, position: 0 }
+    }
+
+    /// Returns the rest of the line that has not been split yet, without leading whitespace
+    pub fn rest(&mut self) -> &'a str {
+        self.skip_whitespace();
+        let rest = &self.input[self.position..];
+        self.position = self.input.len();
+        rest.trim_end()
+    }
+
+    /// Returns the byte offset up to which the line has been split
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the byte offset at which the next word starts
+    pub fn offset(&mut self) -> usize {
+        self.skip_whitespace();
+        self.position
+    }
+
+    /// Returns the next word with its quotes and escapes resolved
+    pub fn next_word(&mut self) -> Result<Option<String>, UnterminatedQuote> {
+        self.skip_whitespace();
+        let mut chars = self.input[self.position..].char_indices().peekable();
+        if chars.peek().is_none() {
+            return Ok(None);
+        }
+        let mut word = String::new();
+        let mut end = self.input.len() - self.position;
+        while let Some((index, c)) = chars.next() {
+            match c {
+                c if c.is_whitespace() => {
+                    end = index;
+                    break;
+                }
+                '\'' => loop {
+                    match chars.next() {
+                        Some((_, '\'')) => break,
+                        Some((_, c)) => word.push(c),
+                        None => return Err(UnterminatedQuote),
+                    }
+                },
+                '"' => loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => word.push(c),
+                            Some((_, 'n')) => word.push('\n'),
+                            Some((_, 'r')) => word.push('\r'),
+                            Some((_, c)) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(UnterminatedQuote),
+                        },
+                        Some((_, c)) => word.push(c),
+                        None => return Err(UnterminatedQuote),
+                    }
+                },
+                '\\' => match chars.next() {
+                    Some((_, c)) => word.push(c),
+                    None => word.push('\\'),
+                },
+                c => word.push(c),
+            }
+        }
+        self.position += end;
+        Ok(Some(word))
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+}
+
+/// Quotes a word if it would not come back unchanged from [`Lexer::next_word`]
+pub(crate) fn quote_word(word: &str) -> Cow<'_, str> {
+    let needs_quotes = word.is_empty()
+        || word
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '\'' | '"' | '\\'));
+    if needs_quotes {
+        Cow::Owned(quote(word))
+    } else {
+        Cow::Borrowed(word)
+    }
+}
+
+/// Quotes the text of a rest-of-line argument if it would not come back unchanged
+///
+/// Such arguments are taken verbatim, so that messages like `it's done` need no quoting, unless
+/// they consist of a single quoted word.
+pub(crate) fn quote_text(text: &str) -> Cow<'_, str> {
+    let verbatim = !text.is_empty()
+        && text.trim() == text
+        && !text.starts_with(['\'', '"'])
+        && !text.contains(['\n', '\r']);
+    if verbatim {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(quote(text))
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Splits a command line into the name of the command, without the leading slash, and the text
+/// of its arguments
+///
+/// This is for commands that are not in the registry, such as those of plugins, which take their
+/// arguments as a single rest-of-line text.
+pub fn split_command(line: &str) -> Result<(String, String), CommandParseError> {
+    let mut lexer = Lexer::new(line);
+    let name = lexer.next_word()?.unwrap_or_default();
+    let name = name.strip_prefix('/').unwrap_or(&name).to_string();
+    let args = unquote_text(lexer.rest()).unwrap_or_default();
+    Ok((name, args))
+}
+
+/// Resolves the text of a rest-of-line argument, the counterpart of [`quote_text`]
+pub(crate) fn unquote_text(rest: &str) -> Option<String> {
+    if rest.is_empty() {
+        return None;
+    }
+    if rest.starts_with(['\'', '"']) {
+        let mut lexer = Lexer::new(rest);
+        if let Ok(Some(word)) = lexer.next_word() {
+            if lexer.rest().is_empty() {
+                return Some(word);
+            }
+        }
+    }
+    Some(rest.to_string())
+}
@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+
+use crate::Username;
+
+/// Whether a user is around and wants to be disturbed
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, Default, Display)]
+pub enum Presence {
+    #[default]
+    #[strum(to_string = "online")]
+    Online,
+    #[strum(to_string = "away")]
+    Away,
+    #[strum(to_string = "do not disturb")]
+    DoNotDisturb,
+}
+
+/// A user entry of the user list
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserInfo {
+    pub username: Username,
+    pub presence: Presence,
+    /// The message the user left when they went away or enabled do not disturb
+    pub away_message: Option<String>,
+    /// How long ago the user last sent anything, in whole seconds
+    pub idle: Duration,
+    /// Whether the user is the bot of a server plugin
+    #[serde(default)]
+    pub bot: bool,
+}
@@ -0,0 +1,217 @@
+//! Every command comes back unchanged from being written out and parsed again
+
+use std::{collections::HashSet, time::Duration};
+
+use chrono::NaiveDate;
+use common::{
+    Capability, Command, CommandParseError, CommandSpec, MessageId, RoomName, SearchQuery, Username,
+};
+use proptest::{
+    prelude::*,
+    strategy::{Union, ValueTree},
+    test_runner::TestRunner,
+};
+use tokio_util::{
+    bytes::BytesMut,
+    codec::{Decoder, Encoder, LinesCodec},
+};
+
+fn room() -> impl Strategy<Value = RoomName> {
+    "[a-z0-9_-]{1,32}".prop_map(|name| RoomName::new(&name).unwrap())
+}
+
+/// Names without whitespace, which may contain quotes and backslashes
+fn username() -> impl Strategy<Value = Username> {
+    r"[^\s]{1,16}".prop_map(Username::new)
+}
+
+fn message_id() -> impl Strategy<Value = MessageId> {
+    any::<u64>().prop_map(MessageId::new)
+}
+
+fn duration() -> impl Strategy<Value = Duration> {
+    (0..10_000_000_000u64, 0..1_000_000_000u32).prop_map(|(secs, nanos)| Duration::new(secs, nanos))
+}
+
+fn capability() -> impl Strategy<Value = Capability> {
+    prop::sample::select(Capability::names()).prop_map(|name| name.parse().unwrap())
+}
+
+fn date() -> impl Strategy<Value = NaiveDate> {
+    (1970..2100i32, 1..=365u32).prop_map(|(year, day)| NaiveDate::from_yo_opt(year, day).unwrap())
+}
+
+fn search_query() -> impl Strategy<Value = SearchQuery> {
+    (
+        prop::collection::vec("[a-z0-9]+", 0..4),
+        prop::option::of(room()),
+        prop::option::of(username()),
+        prop::option::of(date()),
+        1..1000usize,
+    )
+        .prop_map(|(terms, room, from, before, page)| SearchQuery {
+            terms,
+            room,
+            from,
+            before,
+            page,
+        })
+        .prop_filter("a query needs a term or a filter", |query| {
+            !query.terms.is_empty()
+                || query.room.is_some()
+                || query.from.is_some()
+                || query.before.is_some()
+        })
+}
+
+fn command() -> impl Strategy<Value = Command> {
+    let word = any::<String>;
+    let text = any::<String>;
+    Union::new([
+        prop::option::of(word()).prop_map(Command::Help).boxed(),
+        text().prop_map(Command::Complete).boxed(),
+        username().prop_map(Command::ChangeUsername).boxed(),
+        (username(), word())
+            .prop_map(|(name, secret)| Command::Login(name, secret))
+            .boxed(),
+        Just(Command::ListRooms).boxed(),
+        (room(), prop::option::of(message_id()))
+            .prop_map(|(room, id)| Command::MarkRead(room, id))
+            .boxed(),
+        (room(), prop::option::of(word()))
+            .prop_map(|(room, key)| Command::Join(room, key))
+            .boxed(),
+        room().prop_map(Command::Part).boxed(),
+        room().prop_map(Command::Focus).boxed(),
+        (room(), text())
+            .prop_map(|(room, message)| Command::SendMessage(room, message))
+            .boxed(),
+        (message_id(), text())
+            .prop_map(|(id, message)| Command::Edit(id, message))
+            .boxed(),
+        message_id().prop_map(Command::Delete).boxed(),
+        (message_id(), text())
+            .prop_map(|(id, message)| Command::Reply(id, message))
+            .boxed(),
+        message_id().prop_map(Command::Thread).boxed(),
+        search_query().prop_map(Command::Search).boxed(),
+        (message_id(), word())
+            .prop_map(|(id, emoji)| Command::React(id, emoji))
+            .boxed(),
+        (message_id(), word())
+            .prop_map(|(id, emoji)| Command::Unreact(id, emoji))
+            .boxed(),
+        Just(Command::Typing).boxed(),
+        capability().prop_map(Command::Capability).boxed(),
+        prop::option::of(text()).prop_map(Command::Away).boxed(),
+        prop::option::of(text())
+            .prop_map(Command::DoNotDisturb)
+            .boxed(),
+        Just(Command::Back).boxed(),
+        word().prop_map(Command::Highlight).boxed(),
+        word().prop_map(Command::Unhighlight).boxed(),
+        Just(Command::ListHighlights).boxed(),
+        Just(Command::ListUsers).boxed(),
+        (word(), word())
+            .prop_map(|(filename, contents)| Command::SendFile(filename, contents))
+            .boxed(),
+        username().prop_map(Command::Nudge).boxed(),
+        any::<bool>().prop_map(Command::AllowNudges).boxed(),
+        prop::option::of(text()).prop_map(Command::Topic).boxed(),
+        prop::option::of(text()).prop_map(Command::Welcome).boxed(),
+        Just(Command::RoomInfo).boxed(),
+        username().prop_map(Command::Op).boxed(),
+        username().prop_map(Command::Deop).boxed(),
+        (username(), prop::option::of(text()))
+            .prop_map(|(name, reason)| Command::Kick(name, reason))
+            .boxed(),
+        (username(), prop::option::of(duration()))
+            .prop_map(|(name, duration)| Command::Ban(name, duration))
+            .boxed(),
+        username().prop_map(Command::Unban).boxed(),
+        (username(), prop::option::of(duration()))
+            .prop_map(|(name, duration)| Command::Mute(name, duration))
+            .boxed(),
+        username().prop_map(Command::Unmute).boxed(),
+        username().prop_map(Command::Invite).boxed(),
+        any::<bool>().prop_map(Command::InviteOnly).boxed(),
+        prop::option::of(word()).prop_map(Command::Key).boxed(),
+        any::<bool>().prop_map(Command::Persist).boxed(),
+        Just(Command::Quit).boxed(),
+    ])
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2000))]
+
+    #[test]
+    fn commands_round_trip(command in command()) {
+        let line = command.to_string();
+        prop_assert_eq!(Command::try_from(line.clone()), Ok(command), "{}", line);
+    }
+
+    #[test]
+    fn commands_fit_on_a_single_line(command in command()) {
+        let mut codec = LinesCodec::new();
+        let mut buffer = BytesMut::new();
+        codec.encode(command.to_string(), &mut buffer).unwrap();
+        let line = codec.decode(&mut buffer).unwrap().unwrap();
+        prop_assert!(buffer.is_empty(), "{:?} spans several lines", command);
+        prop_assert_eq!(Command::try_from(line), Ok(command));
+    }
+}
+
+#[test]
+fn every_command_is_generated() {
+    let strategy = command();
+    let mut runner = TestRunner::deterministic();
+    let generated: HashSet<&str> = (0..2000)
+        .map(|_| strategy.new_tree(&mut runner).unwrap().current().name())
+        .collect();
+    for spec in CommandSpec::all() {
+        assert!(
+            generated.contains(spec.name),
+            "/{} is not generated",
+            spec.name
+        );
+    }
+}
+
+#[test]
+fn plugin_command_lines_are_split_like_commands() {
+    let split = |line: &str| common::split_command(line).unwrap();
+    assert_eq!(split("/roll"), ("roll".to_string(), String::new()));
+    assert_eq!(
+        split("/roll  2d6 + 1 "),
+        ("roll".to_string(), "2d6 + 1".to_string())
+    );
+    assert_eq!(
+        split("'/deploy' \" v2 \""),
+        ("deploy".to_string(), " v2 ".to_string())
+    );
+    assert!(common::split_command("/roll\" 2d6").is_err());
+}
+
+#[test]
+fn names_must_be_single_words() {
+    for line in ["/name \"\"", "/name \"al ice\"", "/name 'al\tice'"] {
+        assert!(
+            matches!(
+                Command::try_from(line.to_string()),
+                Err(CommandParseError::InvalidValue {
+                    argument: "name",
+                    ..
+                })
+            ),
+            "{line} is accepted"
+        );
+    }
+}
+
+#[test]
+fn line_breaks_are_escaped() {
+    let command = Command::SendMessage(RoomName::lobby(), "two\nlines\r\n".to_string());
+    let line = command.to_string();
+    assert_eq!(line, r#"/msg lobby "two\nlines\r\n""#);
+    assert_eq!(Command::try_from(line), Ok(command));
+}
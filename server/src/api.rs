@@ -0,0 +1,400 @@
+//! An HTTP API for systems that do not speak the line protocol, such as CI jobs
+//!
+//! Every request needs an `Authorization: Bearer <token>` header with one of the configured
+//! tokens. The API sees rooms like a user who has not joined any of them, so private rooms are
+//! left out and cannot be read or posted to. Messages are posted as one of the configured bot
+//! users, whose names are reserved when the server starts.
+
+use std::{collections::HashSet, fmt, net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use common::{MessageId, RoomName, RoomNameError, RoomSummary, ServerEvent, UserInfo, Username};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+
+use crate::{
+    clients::Clients,
+    config::Config,
+    room::{MessageError, Room},
+    rooms::Rooms,
+};
+
+/// The number of history events returned when the request does not say
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+#[derive(Clone)]
+struct Api {
+    rooms: Rooms,
+    clients: Clients,
+    config: Arc<Config>,
+    /// The bot users that messages may be posted as
+    bots: Arc<HashSet<Username>>,
+}
+
+/// The reasons a request can fail
+#[derive(Debug)]
+enum ApiError {
+    Unauthorized,
+    InvalidRoomName(String),
+    /// The bot is not one that the API may post as
+    UnknownBot(Username),
+    /// The room does not exist or is private, which look the same to the API
+    RoomNotFound(RoomName),
+    Message(MessageError),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::Unauthorized => write!(f, "Missing or invalid token"),
+            ApiError::InvalidRoomName(reason) => write!(f, "Invalid room name: {reason}"),
+            ApiError::UnknownBot(username) => write!(f, "Cannot post as \"{username}\""),
+            ApiError::RoomNotFound(room_name) => write!(f, "Room {room_name} not found"),
+            ApiError::Message(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::InvalidRoomName(_) => StatusCode::BAD_REQUEST,
+            ApiError::RoomNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::UnknownBot(_) | ApiError::Message(_) => StatusCode::FORBIDDEN,
+        };
+        let body = Json(serde_json::json!({ "error": self.to_string() }));
+        (status, body).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    /// How many of the most recent events to return
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PostMessage {
+    /// The bot to post as, which has to be one of the configured API bots
+    bot: Username,
+    text: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Posted {
+    id: MessageId,
+}
+
+/// Binds the HTTP listener and serves the API on a task of its own
+pub async fn serve(
+    addr: SocketAddr,
+    rooms: Rooms,
+    clients: Clients,
+    config: Arc<Config>,
+) -> anyhow::Result<()> {
+    if config.api_tokens.is_empty() {
+        tracing::warn!("No API tokens are configured, so all HTTP requests will be refused");
+    }
+    let router = router(Api::new(rooms, clients, config));
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Serving the HTTP API on {}", listener.local_addr()?);
+    tokio::spawn(async move {
+        if let Err(err) = axum::serve(listener, router).await {
+            tracing::error!("HTTP API failed: {err}");
+        }
+    });
+    Ok(())
+}
+
+/// Routes the requests to the API, behind the token check
+fn router(api: Api) -> Router {
+    Router::new()
+        .route("/rooms", get(list_rooms))
+        .route("/rooms/{room}/users", get(list_users))
+        .route("/rooms/{room}/history", get(history))
+        .route("/rooms/{room}/messages", post(post_message))
+        .layer(middleware::from_fn_with_state(api.clone(), authenticate))
+        .with_state(api)
+}
+
+/// Reserves the names of the configured API bots, leaving out those that are already taken
+///
+/// The plugins are loaded before the API, so their bots keep their names.
+fn reserve_bots(clients: &Clients, config: &Config) -> HashSet<Username> {
+    config
+        .api_bots
+        .iter()
+        .filter(|bot| {
+            let reserved = !config.is_admin(bot) && clients.add_bot(bot);
+            if !reserved {
+                tracing::warn!("API bot {bot} is the name of a plugin or an admin, skipping it");
+            }
+            reserved
+        })
+        .cloned()
+        .collect()
+}
+
+/// Refuses requests that do not carry one of the configured tokens
+async fn authenticate(
+    State(api): State<Api>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(ApiError::Unauthorized)?;
+    let valid = api
+        .config
+        .api_tokens
+        .iter()
+        .fold(false, |valid, expected| valid | expected.matches(token));
+    if !valid {
+        return Err(ApiError::Unauthorized);
+    }
+    Ok(next.run(request).await)
+}
+
+impl Api {
+    fn new(rooms: Rooms, clients: Clients, config: Arc<Config>) -> Self {
+        let bots = reserve_bots(&clients, &config);
+        Self {
+            rooms,
+            clients,
+            config,
+            bots: Arc::new(bots),
+        }
+    }
+
+    /// Returns the room with the given name, if the API may see it
+    fn room(&self, room_name: &str) -> Result<Room, ApiError> {
+        let room_name: RoomName = room_name
+            .parse()
+            .map_err(|err: RoomNameError| ApiError::InvalidRoomName(err.to_string()))?;
+        self.rooms
+            .get(&room_name)
+            .filter(|room| !room.is_private())
+            .ok_or(ApiError::RoomNotFound(room_name))
+    }
+}
+
+async fn list_rooms(State(api): State<Api>) -> Json<Vec<RoomSummary>> {
+    Json(api.rooms.list_public())
+}
+
+async fn list_users(
+    State(api): State<Api>,
+    Path(room_name): Path<String>,
+) -> Result<Json<Vec<UserInfo>>, ApiError> {
+    let room = api.room(&room_name)?;
+    let users = room
+        .list_users()
+        .iter()
+        .chain(&api.rooms.plugins().bots(room.name()))
+        .filter_map(|username| api.clients.user_info(username))
+        .collect();
+    Ok(Json(users))
+}
+
+async fn history(
+    State(api): State<Api>,
+    Path(room_name): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<ServerEvent>>, ApiError> {
+    let room = api.room(&room_name)?;
+    let mut events = room.history();
+    let limit = query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    events.drain(..events.len().saturating_sub(limit));
+    Ok(Json(events))
+}
+
+async fn post_message(
+    State(api): State<Api>,
+    Path(room_name): Path<String>,
+    Json(message): Json<PostMessage>,
+) -> Result<(StatusCode, Json<Posted>), ApiError> {
+    let room = api.room(&room_name)?;
+    if !api.bots.contains(&message.bot) {
+        return Err(ApiError::UnknownBot(message.bot));
+    }
+    let id = room
+        .send_message(&message.bot, &message.text)
+        .map_err(ApiError::Message)?;
+    tracing::info!("{} posted message {id} in {room} over HTTP", message.bot);
+    Ok((StatusCode::CREATED, Json(Posted { id })))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use common::RoomEvent;
+    use serde_json::{json, Value};
+    use tokio::sync::broadcast;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{plugins::Plugins, webhooks::Webhooks};
+
+    const TOKEN: &str = "hunter2";
+
+    /// Creates the API of a new server with a token and a bot called `ci`
+    async fn api() -> Api {
+        let config = Arc::new(Config {
+            api_tokens: HashSet::from([TOKEN.parse().unwrap()]),
+            api_bots: HashSet::from([Username::from("ci")]),
+            ..Config::default()
+        });
+        let clients = Clients::default();
+        let (events, _) = broadcast::channel(16);
+        let plugins = Plugins::new(config.clone(), clients.clone()).await;
+        let webhooks = Webhooks::new(config.clone());
+        let rooms = Rooms::new(events, config.clone(), clients.clone(), plugins, webhooks);
+        Api::new(rooms, clients, config)
+    }
+
+    /// Sends a request to the API and returns the status and the JSON body of the response
+    async fn send(api: &Api, request: Request) -> (StatusCode, Value) {
+        let response = router(api.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn get(uri: &str) -> Request {
+        Request::get(uri)
+            .header(AUTHORIZATION, format!("Bearer {TOKEN}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn post(uri: &str, body: Value) -> Request {
+        Request::post(uri)
+            .header(AUTHORIZATION, format!("Bearer {TOKEN}"))
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_valid_token_are_refused() {
+        let api = api().await;
+        let missing = Request::get("/rooms").body(Body::empty()).unwrap();
+        assert_eq!(send(&api, missing).await.0, StatusCode::UNAUTHORIZED);
+        let wrong = Request::get("/rooms")
+            .header(AUTHORIZATION, "Bearer hunter3")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(send(&api, wrong).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(send(&api, get("/rooms")).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn private_and_unknown_rooms_are_not_found() {
+        let api = api().await;
+        let alice = Username::from("alice");
+        let secret: RoomName = "secret".parse().unwrap();
+        api.rooms.join(&alice, &secret, Some("key")).unwrap();
+
+        for uri in [
+            "/rooms/secret/users",
+            "/rooms/secret/history",
+            "/rooms/nowhere/history",
+        ] {
+            assert_eq!(send(&api, get(uri)).await.0, StatusCode::NOT_FOUND, "{uri}");
+        }
+        let message = json!({ "bot": "ci", "text": "hello" });
+        let (status, _) = send(&api, post("/rooms/secret/messages", message)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, rooms) = send(&api, get("/rooms")).await;
+        assert_eq!(rooms.as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn invalid_room_names_are_bad_requests() {
+        let api = api().await;
+        let (status, body) = send(&api, get("/rooms/no%20spaces/history")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid room name"));
+    }
+
+    #[tokio::test]
+    async fn messages_are_posted_as_configured_bots_only() {
+        let api = api().await;
+        let message = json!({ "bot": "mallory", "text": "hello" });
+        let (status, _) = send(&api, post("/rooms/lobby/messages", message)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let message = json!({ "bot": "ci", "text": "build passed" });
+        let (status, body) = send(&api, post("/rooms/lobby/messages", message)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id: MessageId = serde_json::from_value(body["id"].clone()).unwrap();
+        let bot = Username::from("ci");
+        let lobby = api.rooms.get(&RoomName::lobby()).unwrap();
+        assert!(matches!(
+            lobby.history().as_slice(),
+            [ServerEvent::RoomEvent {
+                username,
+                event: RoomEvent::Message(message),
+                ..
+            }] if *username == bot && message.id == id && message.text == "build passed"
+        ));
+    }
+
+    #[tokio::test]
+    async fn history_is_limited_to_the_most_recent_events() {
+        let api = api().await;
+        let lobby = api.rooms.get(&RoomName::lobby()).unwrap();
+        let bot = Username::from("ci");
+        let ids: Vec<MessageId> = (0..3)
+            .map(|i| lobby.send_message(&bot, &i.to_string()).unwrap())
+            .collect();
+
+        let (_, events) = send(&api, get("/rooms/lobby/history")).await;
+        assert_eq!(events.as_array().unwrap().len(), 3);
+        let (_, events) = send(&api, get("/rooms/lobby/history?limit=2")).await;
+        let events: Vec<ServerEvent> = serde_json::from_value(events).unwrap();
+        let listed: Vec<MessageId> = events
+            .iter()
+            .filter_map(|event| match event {
+                ServerEvent::RoomEvent {
+                    event: RoomEvent::Message(message),
+                    ..
+                } => Some(message.id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(listed, ids[1..]);
+    }
+
+    #[test]
+    fn bots_of_plugins_and_admins_are_not_reserved_for_the_api() {
+        let clients = Clients::default();
+        clients.add_bot(&Username::from("dice"));
+        let config = Config {
+            admins: HashSet::from([Username::from("root")]),
+            api_bots: ["ci", "dice", "root"]
+                .into_iter()
+                .map(Username::from)
+                .collect(),
+            ..Config::default()
+        };
+        let bots = reserve_bots(&clients, &config);
+        assert_eq!(bots, HashSet::from([Username::from("ci")]));
+        assert!(clients.is_bot(&Username::from("ci")));
+        assert!(!clients.is_bot(&Username::from("root")));
+    }
+}
@@ -0,0 +1,515 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use common::{Message, Presence, ServerEvent, UserInfo, Username};
+use dashmap::{mapref::entry::Entry, DashMap, DashSet};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// The source of client IDs
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The users that are connected to the server, regardless of the rooms they are in
+#[derive(Clone, Debug, Default)]
+pub struct Clients {
+    inner: Arc<DashMap<Username, Client>>,
+    /// The bot users of plugins, whose names cannot be taken by clients
+    bots: Arc<DashSet<Username>>,
+    /// The names of admins, which clients can only take by logging in
+    reserved: Arc<DashSet<Username>>,
+}
+
+/// Identifies a connected user for as long as they are connected, whatever name they go by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientId(u64);
+
+/// A connected user
+#[derive(Clone, Debug)]
+pub struct Client {
+    id: ClientId,
+    /// The events that are sent directly to the user
+    events: UnboundedSender<ServerEvent>,
+    presence: Presence,
+    /// The message the user left along with their presence
+    away_message: Option<String>,
+    /// Whether the user was marked as away because they went idle
+    auto_away: bool,
+    /// When the user last sent anything
+    last_active: Instant,
+    /// The words that the user wants to be notified about, in lowercase
+    highlights: BTreeSet<String>,
+    /// Whether other users may nudge the user
+    accepts_nudges: bool,
+    /// When the user last nudged someone
+    last_nudge: Option<Instant>,
+}
+
+/// The reasons a nudge can be refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NudgeError {
+    NotFound(Username),
+    OptedOut(Username),
+    DoNotDisturb(Username),
+    /// The sender has to wait for the given time before nudging again
+    Cooldown(Duration),
+}
+
+impl fmt::Display for NudgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NudgeError::NotFound(username) => write!(f, "{username} is not connected"),
+            NudgeError::OptedOut(username) => write!(f, "{username} does not accept nudges"),
+            NudgeError::DoNotDisturb(username) => {
+                write!(f, "{username} does not want to be disturbed")
+            }
+            NudgeError::Cooldown(remaining) => {
+                let remaining = Duration::from_secs(remaining.as_secs().max(1));
+                write!(
+                    f,
+                    "You can nudge again in {}",
+                    humantime::format_duration(remaining)
+                )
+            }
+        }
+    }
+}
+
+impl Client {
+    pub fn new(events: UnboundedSender<ServerEvent>) -> Self {
+        Self {
+            id: ClientId(NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed)),
+            events,
+            presence: Presence::Online,
+            away_message: None,
+            auto_away: false,
+            last_active: Instant::now(),
+            highlights: BTreeSet::new(),
+            accepts_nudges: true,
+            last_nudge: None,
+        }
+    }
+}
+
+impl Clients {
+    /// The number of random names to try before falling back to numbered names
+    const RANDOM_NAME_ATTEMPTS: usize = 16;
+
+    /// Reserves the name of the bot user of a plugin, returning `false` if a user has it
+    pub fn add_bot(&self, username: &Username) -> bool {
+        if self.inner.contains_key(username) {
+            return false;
+        }
+        self.bots.insert(username.clone())
+    }
+
+    pub fn remove_bot(&self, username: &Username) {
+        self.bots.remove(username);
+    }
+
+    pub fn is_bot(&self, username: &Username) -> bool {
+        self.bots.contains(username)
+    }
+
+    /// Reserves a name so that it can only be taken through [`Clients::claim`]
+    pub fn reserve(&self, username: &Username) {
+        self.reserved.insert(username.clone());
+    }
+
+    pub fn is_reserved(&self, username: &Username) -> bool {
+        self.reserved.contains(username)
+    }
+
+    /// Registers the client under the given name, returning `false` if it is taken or reserved
+    pub fn insert(&self, username: &Username, client: Client) -> bool {
+        !self.is_reserved(username) && self.insert_any(username, client)
+    }
+
+    /// Registers the client under the given name, even if it is reserved
+    fn insert_any(&self, username: &Username, client: Client) -> bool {
+        if self.is_bot(username) {
+            return false;
+        }
+        match self.inner.entry(username.clone()) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(client);
+                true
+            }
+        }
+    }
+
+    /// Registers the client under a random name that is not taken yet and returns it
+    pub fn insert_random(&self, client: Client) -> Username {
+        for _ in 0..Self::RANDOM_NAME_ATTEMPTS {
+            let username = Username::random();
+            if self.insert(&username, client.clone()) {
+                return username;
+            }
+        }
+        let base = Username::random();
+        (2..)
+            .map(|n| Username::new(format!("{base}{n}")))
+            .find(|username| self.insert(username, client.clone()))
+            .expect("ran out of usernames")
+    }
+
+    /// Renames a user, returning `false` if the new name is taken or reserved
+    pub fn rename(&self, old_name: &Username, new_name: &Username) -> bool {
+        !self.is_reserved(new_name) && self.claim(old_name, new_name)
+    }
+
+    /// Renames a user, even to a reserved name, returning `false` if the new name is taken
+    ///
+    /// The caller has to make sure that the user may take the name. The new name is claimed
+    /// before the old one is released so that no other user can grab either of them in between.
+    pub fn claim(&self, old_name: &Username, new_name: &Username) -> bool {
+        let Some(client) = self.inner.get(old_name).map(|client| client.clone()) else {
+            return false;
+        };
+        if !self.insert_any(new_name, client) {
+            return false;
+        }
+        self.inner.remove(old_name);
+        true
+    }
+
+    pub fn remove(&self, username: &Username) -> bool {
+        self.inner.remove(username).is_some()
+    }
+
+    pub fn contains(&self, username: &Username) -> bool {
+        self.inner.contains_key(username)
+    }
+
+    /// Returns the ID of a connected user, which stays the same when they change their name
+    pub fn id(&self, username: &Username) -> Option<ClientId> {
+        self.inner.get(username).map(|client| client.id)
+    }
+
+    pub fn usernames(&self) -> Vec<Username> {
+        self.inner.iter().map(|entry| entry.key().clone()).collect()
+    }
+
+    /// Returns the user entry of a connected user or a bot, which is always online
+    pub fn user_info(&self, username: &Username) -> Option<UserInfo> {
+        if self.is_bot(username) {
+            return Some(UserInfo {
+                username: username.clone(),
+                presence: Presence::Online,
+                away_message: None,
+                idle: Duration::ZERO,
+                bot: true,
+            });
+        }
+        self.inner.get(username).map(|client| UserInfo {
+            username: username.clone(),
+            presence: client.presence,
+            away_message: client.away_message.clone(),
+            idle: Duration::from_secs(client.last_active.elapsed().as_secs()),
+            bot: false,
+        })
+    }
+
+    /// Sets the presence of the user, returning `false` if they are not connected
+    pub fn set_presence(
+        &self,
+        username: &Username,
+        presence: Presence,
+        away_message: Option<String>,
+    ) -> bool {
+        let Some(mut client) = self.inner.get_mut(username) else {
+            return false;
+        };
+        client.presence = presence;
+        client.away_message = away_message;
+        client.auto_away = false;
+        true
+    }
+
+    /// Records activity of the user, returning `true` if that brought them back from idling
+    pub fn touch(&self, username: &Username) -> bool {
+        let Some(mut client) = self.inner.get_mut(username) else {
+            return false;
+        };
+        client.last_active = Instant::now();
+        if !client.auto_away {
+            return false;
+        }
+        client.presence = Presence::Online;
+        client.auto_away = false;
+        true
+    }
+
+    /// Returns when an online user would go idle after the given time without activity
+    pub fn idle_at(&self, username: &Username, after: Duration) -> Option<Instant> {
+        self.inner
+            .get(username)
+            .filter(|client| client.presence == Presence::Online)
+            .map(|client| client.last_active + after)
+    }
+
+    /// Marks an online user as away if they have been inactive for the given time
+    ///
+    /// Returns whether the presence of the user changed.
+    pub fn go_idle(&self, username: &Username, after: Duration) -> bool {
+        let Some(mut client) = self.inner.get_mut(username) else {
+            return false;
+        };
+        if client.presence != Presence::Online || client.last_active.elapsed() < after {
+            return false;
+        }
+        client.presence = Presence::Away;
+        client.auto_away = true;
+        true
+    }
+
+    /// Adds or removes a highlight keyword of the user and returns their keywords afterwards
+    pub fn highlight(&self, username: &Username, keyword: &str, add: bool) -> Vec<String> {
+        let Some(mut client) = self.inner.get_mut(username) else {
+            return Vec::new();
+        };
+        if add {
+            client.highlights.insert(keyword.to_lowercase());
+        } else {
+            client.highlights.remove(&keyword.to_lowercase());
+        }
+        client.highlights.iter().cloned().collect()
+    }
+
+    pub fn highlights(&self, username: &Username) -> Vec<String> {
+        self.inner
+            .get(username)
+            .map(|client| client.highlights.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn set_accepts_nudges(&self, username: &Username, accepts_nudges: bool) {
+        if let Some(mut client) = self.inner.get_mut(username) {
+            client.accepts_nudges = accepts_nudges;
+        }
+    }
+
+    /// Sends a nudge to the target, unless they do not want one or the sender nudged too recently
+    ///
+    /// Refused nudges do not count towards the cooldown of the sender.
+    pub fn nudge(
+        &self,
+        from: &Username,
+        to: &Username,
+        cooldown: Duration,
+    ) -> Result<(), NudgeError> {
+        // The target is checked before the sender is locked, as both may live in the same shard
+        match self.inner.get(to) {
+            None => return Err(NudgeError::NotFound(to.clone())),
+            Some(target) if !target.accepts_nudges => return Err(NudgeError::OptedOut(to.clone())),
+            Some(target) if target.presence == Presence::DoNotDisturb => {
+                return Err(NudgeError::DoNotDisturb(to.clone()))
+            }
+            Some(_) => {}
+        }
+        if let Some(mut sender) = self.inner.get_mut(from) {
+            let elapsed = sender.last_nudge.map(|last_nudge| last_nudge.elapsed());
+            if let Some(elapsed) = elapsed.filter(|elapsed| *elapsed < cooldown) {
+                return Err(NudgeError::Cooldown(cooldown - elapsed));
+            }
+            sender.last_nudge = Some(Instant::now());
+        }
+        self.send(to, ServerEvent::nudge(from));
+        Ok(())
+    }
+
+    /// Returns the users that the message mentions or highlights, other than the author
+    ///
+    /// Each user comes with the keyword that matched, or `None` if they were mentioned by name.
+    /// Users who do not want to be disturbed are left out.
+    pub fn mentioned(
+        &self,
+        author: &Username,
+        message: &Message,
+    ) -> Vec<(Username, Option<String>)> {
+        self.inner
+            .iter()
+            .filter(|entry| entry.key() != author)
+            .filter(|entry| entry.presence != Presence::DoNotDisturb)
+            .filter_map(|entry| {
+                let username = entry.key();
+                if message.mentions(username) {
+                    return Some((username.clone(), None));
+                }
+                let keyword = entry
+                    .highlights
+                    .iter()
+                    .find(|keyword| message.contains_word(keyword))?;
+                Some((username.clone(), Some(keyword.clone())))
+            })
+            .collect()
+    }
+
+    /// Sends an event directly to the user, returning `false` if they are not connected
+    pub fn send(&self, username: &Username, event: ServerEvent) -> bool {
+        self.inner
+            .get(username)
+            .is_some_and(|client| client.events.send(event).is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    fn client() -> Client {
+        Client::new(mpsc::unbounded_channel().0)
+    }
+
+    #[test]
+    fn random_names_are_unique() {
+        let clients = Clients::default();
+        let names: HashSet<Username> = (0..500).map(|_| clients.insert_random(client())).collect();
+        assert_eq!(names.len(), 500);
+        assert!(names.iter().all(|name| clients.contains(name)));
+    }
+
+    #[test]
+    fn rename_refuses_taken_names() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        let bob = Username::from("bob");
+        assert!(clients.insert(&alice, client()));
+        assert!(clients.insert(&bob, client()));
+        assert!(!clients.insert(&alice, client()));
+        assert!(!clients.rename(&alice, &bob));
+        assert!(clients.contains(&alice));
+        assert!(clients.contains(&bob));
+    }
+
+    #[test]
+    fn rename_releases_the_old_name() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        let carol = Username::from("carol");
+        assert!(clients.insert(&alice, client()));
+        assert!(clients.rename(&alice, &carol));
+        assert!(!clients.contains(&alice));
+        assert!(clients.contains(&carol));
+        assert!(clients.insert(&alice, client()));
+    }
+
+    #[test]
+    fn remove_releases_the_name() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        assert!(clients.insert(&alice, client()));
+        assert!(clients.remove(&alice));
+        assert!(!clients.contains(&alice));
+        assert!(clients.insert(&alice, client()));
+    }
+
+    #[test]
+    fn reserved_names_can_only_be_claimed() {
+        let clients = Clients::default();
+        let admin = Username::from("admin");
+        clients.reserve(&admin);
+        assert!(!clients.insert(&admin, client()));
+
+        let user = clients.insert_random(client());
+        assert_ne!(user, admin);
+        assert!(!clients.rename(&user, &admin));
+        assert!(clients.contains(&user));
+
+        assert!(clients.claim(&user, &admin));
+        assert!(clients.contains(&admin));
+        assert!(!clients.contains(&user));
+    }
+
+    #[test]
+    fn nudges_respect_the_cooldown_and_the_wishes_of_the_target() {
+        let clients = Clients::default();
+        let [alice, bob, carol] = ["alice", "bob", "carol"].map(Username::from);
+        let (events, mut nudged) = mpsc::unbounded_channel();
+        clients.insert(&alice, client());
+        clients.insert(&bob, Client::new(events));
+        clients.insert(&carol, client());
+        let cooldown = Duration::from_secs(60);
+
+        assert_eq!(clients.nudge(&alice, &bob, cooldown), Ok(()));
+        assert!(matches!(
+            nudged.try_recv(),
+            Ok(ServerEvent::Nudge { from }) if from == alice
+        ));
+        assert!(matches!(
+            clients.nudge(&alice, &carol, cooldown),
+            Err(NudgeError::Cooldown(remaining)) if remaining <= cooldown
+        ));
+
+        clients.set_accepts_nudges(&bob, false);
+        assert_eq!(
+            clients.nudge(&carol, &bob, cooldown),
+            Err(NudgeError::OptedOut(bob.clone()))
+        );
+        clients.set_accepts_nudges(&bob, true);
+        clients.set_presence(&bob, Presence::DoNotDisturb, None);
+        assert_eq!(
+            clients.nudge(&carol, &bob, cooldown),
+            Err(NudgeError::DoNotDisturb(bob.clone()))
+        );
+        assert!(nudged.try_recv().is_err());
+
+        // Refused nudges do not hold up the next one
+        clients.set_presence(&bob, Presence::Online, None);
+        assert_eq!(clients.nudge(&carol, &bob, cooldown), Ok(()));
+    }
+
+    #[test]
+    fn idle_users_go_away_until_they_come_back() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        clients.insert(&alice, client());
+        let presence = |username| clients.user_info(username).unwrap().presence;
+
+        let long = Duration::from_secs(3600);
+        assert!(clients.idle_at(&alice, long).unwrap() > Instant::now());
+        assert!(!clients.go_idle(&alice, long));
+        assert!(clients.go_idle(&alice, Duration::ZERO));
+        assert_eq!(presence(&alice), Presence::Away);
+        assert_eq!(clients.idle_at(&alice, Duration::ZERO), None);
+        assert!(!clients.go_idle(&alice, Duration::ZERO));
+
+        assert!(clients.touch(&alice));
+        assert_eq!(presence(&alice), Presence::Online);
+        assert!(!clients.touch(&alice));
+    }
+
+    #[test]
+    fn activity_does_not_end_a_chosen_away_status() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        clients.insert(&alice, client());
+
+        clients.set_presence(&alice, Presence::Away, Some("lunch".to_string()));
+        assert!(!clients.go_idle(&alice, Duration::ZERO));
+        assert!(!clients.touch(&alice));
+        let info = clients.user_info(&alice).unwrap();
+        assert_eq!(info.presence, Presence::Away);
+        assert_eq!(info.away_message.as_deref(), Some("lunch"));
+
+        // Choosing a presence takes over from going idle
+        clients.set_presence(&alice, Presence::Online, None);
+        assert!(clients.go_idle(&alice, Duration::ZERO));
+        clients.set_presence(&alice, Presence::DoNotDisturb, None);
+        assert!(!clients.touch(&alice));
+        assert_eq!(
+            clients.user_info(&alice).unwrap().presence,
+            Presence::DoNotDisturb
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    fmt,
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use common::{RoomName, Username};
+use reqwest::Url;
+
+/// Server-wide settings that are shared by all connections
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// Room names that only admins are allowed to create
+    pub reserved_rooms: HashSet<RoomName>,
+    /// Users with administrative privileges, whose names can only be taken with the secret
+    pub admins: HashSet<Username>,
+    /// The secret that users have to give to take an admin name
+    pub admin_secret: Option<Secret>,
+    /// Rooms that exist from the start and are kept when they become empty
+    pub persistent_rooms: HashSet<RoomName>,
+    /// How long empty rooms are kept around before they are deleted
+    pub room_grace_period: Option<Duration>,
+    /// How long users can be inactive before they are marked as away
+    pub auto_away: Option<Duration>,
+    /// How long users have to wait between two nudges
+    pub nudge_cooldown: Duration,
+    /// The rooms that each plugin is enabled in, by the name of the plugin
+    pub plugins: HashMap<String, HashSet<RoomName>>,
+    /// The directory that scripted plugins are loaded from
+    pub scripts_dir: Option<PathBuf>,
+    /// The endpoints that the events of each room are posted to
+    pub webhooks: HashMap<RoomName, Vec<Url>>,
+    /// The key that webhook requests are signed with
+    pub webhook_secret: Option<Secret>,
+    /// The file that webhook events are appended to when they cannot be delivered
+    pub webhook_dead_letters: Option<PathBuf>,
+    /// The address to serve the HTTP API on, which is disabled if not set
+    pub http_address: Option<SocketAddr>,
+    /// The tokens that grant access to the HTTP API
+    pub api_tokens: HashSet<Secret>,
+    /// The bot users that the HTTP API may post as
+    pub api_bots: HashSet<Username>,
+}
+
+impl Config {
+    /// Returns whether the given room name is reserved for admins
+    pub fn is_reserved(&self, room_name: &RoomName) -> bool {
+        self.reserved_rooms.contains(room_name)
+    }
+
+    /// Returns whether the given user is an admin
+    pub fn is_admin(&self, username: &Username) -> bool {
+        self.admins.contains(username)
+    }
+
+    /// Returns whether the given secret is the one that admins log in with
+    pub fn is_admin_secret(&self, secret: &str) -> bool {
+        self.admin_secret
+            .as_ref()
+            .is_some_and(|admin_secret| admin_secret.matches(secret))
+    }
+
+    /// Returns whether the plugin with the given name is enabled in the room
+    pub fn is_plugin_enabled(&self, plugin: &str, room_name: &RoomName) -> bool {
+        self.plugins
+            .get(plugin)
+            .is_some_and(|rooms| rooms.contains(room_name))
+    }
+}
+
+/// A password, token or key, which is left out when the configuration is logged
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Returns the secret itself, for the few places that need it, such as signing
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Compares the secret in a time that does not depend on where it differs from the candidate
+    pub fn matches(&self, candidate: &str) -> bool {
+        self.0.len() == candidate.len()
+            && self
+                .0
+                .bytes()
+                .zip(candidate.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret(..)")
+    }
+}
+
+impl FromStr for Secret {
+    type Err = Infallible;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_left_out_of_the_debug_output() {
+        let config = Config {
+            admin_secret: Some("admin-secret".parse().unwrap()),
+            webhook_secret: Some("webhook-secret".parse().unwrap()),
+            api_tokens: HashSet::from(["api-token".parse().unwrap()]),
+            ..Config::default()
+        };
+        let debug = format!("{config:?}");
+        for secret in ["admin-secret", "webhook-secret", "api-token"] {
+            assert!(!debug.contains(secret), "{secret} is in {debug}");
+        }
+    }
+}
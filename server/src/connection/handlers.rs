@@ -0,0 +1,522 @@
+//! The handlers that run the built-in commands, one for each entry in the registry
+
+use common::{Command, CommandSpec, Presence, ServerEvent};
+use futures::future::BoxFuture;
+
+use super::{Connection, ConnectionState};
+use crate::{room::MessageError, server::commands_help};
+
+/// Runs a parsed command on behalf of the user of the connection
+pub(super) type Handler = for<'a> fn(&'a mut Connection, Command) -> BoxFuture<'a, ()>;
+
+/// Declares the handler of a command, which takes the fields of its [`Command`] variant
+///
+/// Handlers are looked up by the name of the command, so the variant always matches.
+macro_rules! handler {
+    (|$connection:ident, $command:pat_param| $body:expr) => {{
+        fn handle(connection: &mut Connection, command: Command) -> BoxFuture<'_, ()> {
+            Box::pin(async move {
+                let $command = command else {
+                    unreachable!("/{} is not handled here", command.name());
+                };
+                let $connection = connection;
+                $body
+            })
+        }
+        handle as Handler
+    }};
+}
+
+/// The handlers of the built-in commands, keyed by the name of their entry in the registry
+static HANDLERS: &[(&str, Handler)] = &[
+    (
+        "help",
+        handler!(|connection, Command::Help(name)| match name {
+            None => {
+                let help = ServerEvent::help(
+                    &connection.username,
+                    commands_help(connection.rooms.plugins()),
+                );
+                connection.send_event(help).await;
+            }
+            Some(name) => {
+                let name = name.trim_start_matches('/');
+                match connection.command_info(name) {
+                    Some(info) => connection.send_event(ServerEvent::command_info(info)).await,
+                    None => {
+                        let message = format!("Unknown command: /{name}");
+                        connection.send_event(ServerEvent::error(&message)).await;
+                    }
+                }
+            }
+        }),
+    ),
+    (
+        "complete",
+        handler!(|connection, Command::Complete(line)| {
+            let completions = connection.complete(&line);
+            connection.send_event(completions).await;
+        }),
+    ),
+    (
+        "name",
+        handler!(|connection, Command::ChangeUsername(new_name)| {
+            if connection.clients.is_reserved(&new_name) {
+                let message = format!("{new_name} is reserved, use /login to take it");
+                connection.send_event(ServerEvent::error(&message)).await;
+            } else {
+                let renamed = connection.clients.rename(&connection.username, &new_name);
+                connection.rename(new_name, renamed).await;
+            }
+        }),
+    ),
+    (
+        "login",
+        handler!(|connection, Command::Login(name, secret)| {
+            if !connection.config.is_admin(&name) {
+                let message = format!("{name} is not an admin");
+                connection.send_event(ServerEvent::error(&message)).await;
+            } else if !connection.config.is_admin_secret(&secret) {
+                tracing::warn!("Failed login as {name}");
+                connection
+                    .send_event(ServerEvent::error("Wrong secret"))
+                    .await;
+            } else {
+                let renamed = connection.clients.claim(&connection.username, &name);
+                connection.rename(name, renamed).await;
+            }
+        }),
+    ),
+    (
+        "rooms",
+        handler!(|connection, Command::ListRooms| {
+            let rooms_list = connection.rooms.list(&connection.username);
+            connection.send_event(ServerEvent::rooms(rooms_list)).await;
+        }),
+    ),
+    (
+        "join",
+        handler!(|connection, Command::Join(room_name, key)| {
+            connection.join_room(&room_name, key.as_deref()).await;
+        }),
+    ),
+    (
+        "part",
+        handler!(|connection, Command::Part(room_name)| {
+            if !connection.joined.contains_key(&room_name) {
+                let message = format!("You are not in {room_name}");
+                connection.send_event(ServerEvent::error(&message)).await;
+            } else if connection.joined.len() == 1 {
+                let error = ServerEvent::error("You cannot leave your last room");
+                connection.send_event(error).await;
+            } else {
+                connection.leave_room(&room_name).await;
+            }
+        }),
+    ),
+    (
+        "focus",
+        handler!(|connection, Command::Focus(room_name)| {
+            connection.focus_room(&room_name).await;
+        }),
+    ),
+    (
+        "msg",
+        handler!(|connection, Command::SendMessage(room_name, message)| {
+            let result = match connection.joined.get(&room_name) {
+                Some(room) => room
+                    .send_message(&connection.username, &message)
+                    .map_err(|err| err.to_string()),
+                None => Err(format!("You are not in {room_name}")),
+            };
+            if let Err(err) = result {
+                connection.send_event(ServerEvent::error(&err)).await;
+            }
+        }),
+    ),
+    (
+        "markread",
+        handler!(|connection, Command::MarkRead(room_name, id)| {
+            let result = match connection.joined.get(&room_name) {
+                Some(room) => room
+                    .mark_read(&connection.username, id)
+                    .map_err(|err| err.to_string()),
+                None => Err(format!("You are not in {room_name}")),
+            };
+            match result {
+                Ok(()) => {
+                    let rooms_list = connection.rooms.list(&connection.username);
+                    connection.send_event(ServerEvent::rooms(rooms_list)).await;
+                }
+                Err(err) => connection.send_event(ServerEvent::error(&err)).await,
+            }
+        }),
+    ),
+    (
+        "edit",
+        handler!(|connection, Command::Edit(id, text)| {
+            let result = match connection.room_with_message(id) {
+                Some(room) => {
+                    let moderator = connection.can_moderate(&room);
+                    room.edit_message(&connection.username, id, &text, moderator)
+                }
+                None => Err(MessageError::NotFound(id)),
+            };
+            if let Err(err) = result {
+                connection
+                    .send_event(ServerEvent::error(&err.to_string()))
+                    .await;
+            }
+        }),
+    ),
+    (
+        "delete",
+        handler!(|connection, Command::Delete(id)| {
+            let result = match connection.room_with_message(id) {
+                Some(room) => {
+                    let moderator = connection.can_moderate(&room);
+                    room.delete_message(&connection.username, id, moderator)
+                }
+                None => Err(MessageError::NotFound(id)),
+            };
+            if let Err(err) = result {
+                connection
+                    .send_event(ServerEvent::error(&err.to_string()))
+                    .await;
+            }
+        }),
+    ),
+    (
+        "reply",
+        handler!(|connection, Command::Reply(id, text)| {
+            let result = match connection.room_with_message(id) {
+                Some(room) => room.reply(&connection.username, id, &text).map(|_| ()),
+                None => Err(MessageError::NotFound(id)),
+            };
+            if let Err(err) = result {
+                connection
+                    .send_event(ServerEvent::error(&err.to_string()))
+                    .await;
+            }
+        }),
+    ),
+    (
+        "thread",
+        handler!(
+            |connection, Command::Thread(id)| match connection.room_with_message(id) {
+                Some(room) => {
+                    let (root, events) = room.thread(id);
+                    let thread = ServerEvent::thread(room.name(), root, events);
+                    connection.send_event(thread).await;
+                }
+                None => {
+                    let error = MessageError::NotFound(id).to_string();
+                    connection.send_event(ServerEvent::error(&error)).await;
+                }
+            }
+        ),
+    ),
+    (
+        "search",
+        handler!(|connection, Command::Search(query)| match connection
+            .rooms
+            .search(&connection.username, &query)
+        {
+            Ok(results) => connection.send_event(results).await,
+            Err(err) =>
+                connection
+                    .send_event(ServerEvent::error(&err.to_string()))
+                    .await,
+        }),
+    ),
+    (
+        "react",
+        handler!(|connection, Command::React(id, emoji)| {
+            connection.react(id, &emoji, true).await;
+        }),
+    ),
+    (
+        "unreact",
+        handler!(|connection, Command::Unreact(id, emoji)| {
+            connection.react(id, &emoji, false).await;
+        }),
+    ),
+    (
+        "typing",
+        handler!(|connection, Command::Typing| {
+            connection.room.start_typing(&connection.username);
+        }),
+    ),
+    (
+        "cap",
+        handler!(|connection, Command::Capability(capability)| {
+            connection.capabilities.insert(capability);
+        }),
+    ),
+    (
+        "away",
+        handler!(|connection, Command::Away(message)| {
+            connection
+                .clients
+                .set_presence(&connection.username, Presence::Away, message);
+            connection.broadcast_presence();
+        }),
+    ),
+    (
+        "dnd",
+        handler!(|connection, Command::DoNotDisturb(message)| {
+            connection
+                .clients
+                .set_presence(&connection.username, Presence::DoNotDisturb, message);
+            connection.broadcast_presence();
+        }),
+    ),
+    (
+        "back",
+        handler!(|connection, Command::Back| {
+            connection
+                .clients
+                .set_presence(&connection.username, Presence::Online, None);
+            connection.broadcast_presence();
+        }),
+    ),
+    (
+        "highlight",
+        handler!(|connection, Command::Highlight(keyword)| {
+            let keywords = connection
+                .clients
+                .highlight(&connection.username, &keyword, true);
+            connection
+                .send_event(ServerEvent::highlights(keywords))
+                .await;
+        }),
+    ),
+    (
+        "unhighlight",
+        handler!(|connection, Command::Unhighlight(keyword)| {
+            let keywords = connection
+                .clients
+                .highlight(&connection.username, &keyword, false);
+            connection
+                .send_event(ServerEvent::highlights(keywords))
+                .await;
+        }),
+    ),
+    (
+        "highlights",
+        handler!(|connection, Command::ListHighlights| {
+            let keywords = connection.clients.highlights(&connection.username);
+            connection
+                .send_event(ServerEvent::highlights(keywords))
+                .await;
+        }),
+    ),
+    (
+        "users",
+        handler!(|connection, Command::ListUsers| {
+            connection.send_users().await;
+        }),
+    ),
+    (
+        "file",
+        handler!(|connection, Command::SendFile(filename, contents)| {
+            if let Err(err) = connection
+                .room
+                .send_file(&connection.username, &filename, &contents)
+            {
+                connection
+                    .send_event(ServerEvent::error(&err.to_string()))
+                    .await;
+            }
+        }),
+    ),
+    (
+        "nudge",
+        handler!(|connection, Command::Nudge(target)| {
+            let cooldown = connection.config.nudge_cooldown;
+            match connection
+                .clients
+                .nudge(&connection.username, &target, cooldown)
+            {
+                Ok(()) => {
+                    connection
+                        .send_event(ServerEvent::nudge_sent(&target))
+                        .await
+                }
+                Err(err) => {
+                    connection
+                        .send_event(ServerEvent::error(&err.to_string()))
+                        .await
+                }
+            }
+        }),
+    ),
+    (
+        "nudges",
+        handler!(|connection, Command::AllowNudges(accepts_nudges)| {
+            connection
+                .clients
+                .set_accepts_nudges(&connection.username, accepts_nudges);
+        }),
+    ),
+    (
+        "topic",
+        handler!(|connection, Command::Topic(topic)| match topic {
+            None => {
+                connection.send_room_info().await;
+            }
+            Some(topic) => {
+                if connection.ensure_op().await {
+                    let topic = Some(topic).filter(|topic| !topic.is_empty());
+                    connection.room.set_topic(&connection.username, topic);
+                }
+            }
+        }),
+    ),
+    (
+        "welcome",
+        handler!(|connection, Command::Welcome(welcome)| {
+            connection.room.set_welcome(welcome);
+            connection.send_room_info().await;
+        }),
+    ),
+    (
+        "roominfo",
+        handler!(|connection, Command::RoomInfo| {
+            connection.send_room_info().await;
+        }),
+    ),
+    (
+        "op",
+        handler!(|connection, Command::Op(target)| {
+            if connection.ensure_in_room(&target).await {
+                connection.room.op(&connection.username, &target);
+            }
+        }),
+    ),
+    (
+        "deop",
+        handler!(|connection, Command::Deop(target)| {
+            connection.room.deop(&connection.username, &target);
+        }),
+    ),
+    (
+        "kick",
+        handler!(|connection, Command::Kick(target, reason)| {
+            if connection.room.is_lobby() {
+                let error = ServerEvent::error("Users cannot be kicked from the lobby");
+                connection.send_event(error).await;
+            } else if connection.ensure_in_room(&target).await {
+                connection
+                    .room
+                    .kick(&connection.username, &target, reason.as_deref());
+            }
+        }),
+    ),
+    (
+        "ban",
+        handler!(|connection, Command::Ban(target, duration)| {
+            if connection.room.is_lobby() {
+                let error = ServerEvent::error("Users cannot be banned from the lobby");
+                connection.send_event(error).await;
+            } else if !connection.room.ban(&connection.username, &target, duration) {
+                let message = format!("{target} is not connected");
+                connection.send_event(ServerEvent::error(&message)).await;
+            }
+        }),
+    ),
+    (
+        "unban",
+        handler!(|connection, Command::Unban(target)| {
+            if !connection.room.unban(&connection.username, &target) {
+                let message = format!("{target} is not banned");
+                connection.send_event(ServerEvent::error(&message)).await;
+            }
+        }),
+    ),
+    (
+        "mute",
+        handler!(|connection, Command::Mute(target, duration)| {
+            if connection.ensure_in_room(&target).await {
+                connection
+                    .room
+                    .mute(&connection.username, &target, duration);
+            }
+        }),
+    ),
+    (
+        "unmute",
+        handler!(|connection, Command::Unmute(target)| {
+            if !connection.room.unmute(&connection.username, &target) {
+                let message = format!("{target} is not muted");
+                connection.send_event(ServerEvent::error(&message)).await;
+            }
+        }),
+    ),
+    (
+        "invite",
+        handler!(|connection, Command::Invite(target)| {
+            connection.invite(&target).await;
+        }),
+    ),
+    (
+        "inviteonly",
+        handler!(|connection, Command::InviteOnly(invite_only)| {
+            connection.room.set_invite_only(invite_only);
+            connection.send_room_info().await;
+        }),
+    ),
+    (
+        "key",
+        handler!(|connection, Command::Key(key)| {
+            connection.room.set_key(key);
+            connection.send_room_info().await;
+        }),
+    ),
+    (
+        "persist",
+        handler!(|connection, Command::Persist(persistent)| {
+            if connection.room.is_lobby() {
+                let error = ServerEvent::error("The lobby is always persistent");
+                connection.send_event(error).await;
+            } else {
+                connection.room.set_persistent(persistent);
+                connection.send_room_info().await;
+            }
+        }),
+    ),
+    (
+        "quit",
+        handler!(|connection, Command::Quit| {
+            connection.send_event(ServerEvent::Disconnect).await;
+            connection.state = ConnectionState::Disconnected;
+        }),
+    ),
+];
+
+/// Returns the handler of a built-in command
+pub(super) fn find(spec: &CommandSpec) -> Option<Handler> {
+    HANDLERS
+        .iter()
+        .find(|(name, _)| *name == spec.name)
+        .map(|(_, handler)| *handler)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn every_command_has_a_single_handler() {
+        for spec in CommandSpec::all() {
+            let handlers = HANDLERS.iter().filter(|(name, _)| *name == spec.name);
+            assert_eq!(handlers.count(), 1, "/{} has no single handler", spec.name);
+        }
+        let names: HashSet<&str> = CommandSpec::all().iter().map(|spec| spec.name).collect();
+        for (name, _) in HANDLERS {
+            assert!(names.contains(name), "/{name} is not in the registry");
+        }
+    }
+}
@@ -0,0 +1,866 @@
+use std::{
+    collections::{HashMap, HashSet},
+    iter,
+    net::SocketAddr,
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::Context;
+use common::{
+    split_command, ArgKind, Capability, Command, CommandInfo, CommandParseError, CommandSpec,
+    Completion, CompletionTarget, MessageId, Permission, RoomEvent, RoomName, ServerEvent,
+    Username,
+};
+use futures::SinkExt;
+use tokio::{
+    net::TcpStream,
+    sync::{
+        broadcast::Receiver,
+        mpsc::{self, UnboundedReceiver},
+    },
+};
+use tokio_stream::{wrappers::BroadcastStream, StreamExt, StreamMap};
+use tokio_util::codec::{Framed, LinesCodec};
+use tracing::instrument;
+
+use crate::{
+    clients::{Client, Clients},
+    config::Config,
+    plugin::Bot,
+    room::{MessageError, Room},
+    rooms::Rooms,
+    server::commands_help,
+};
+
+mod handlers;
+
+pub struct Connection {
+    /// The events that are come from the user
+    user_events: Framed<TcpStream, LinesCodec>,
+    /// The events that are broadcasted to all users
+    server_events: Receiver<ServerEvent>,
+    /// The events that are broadcasted to the rooms the user is in
+    room_events: StreamMap<RoomName, BroadcastStream<ServerEvent>>,
+    /// The events that are sent directly to the user
+    direct_events: UnboundedReceiver<ServerEvent>,
+    /// The users that are connected to the server
+    clients: Clients,
+    /// The rooms that are available on the server
+    rooms: Rooms,
+    /// The settings of the server
+    config: Arc<Config>,
+    /// The username of the connected user
+    username: Username,
+    /// The address of the connected user
+    addr: SocketAddr,
+    /// The current state of the connection
+    state: ConnectionState,
+    /// The rooms that the user is in
+    joined: HashMap<RoomName, Room>,
+    /// The room that the user is focused on, which receives their plain messages
+    room: Room,
+    /// The optional features that the client announced support for
+    capabilities: HashSet<Capability>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConnectionState {
+    Connected,
+    Disconnected,
+}
+
+impl Connection {
+    pub fn new(
+        tcp: TcpStream,
+        server_events: Receiver<ServerEvent>,
+        clients: Clients,
+        rooms: Rooms,
+        config: Arc<Config>,
+        addr: SocketAddr,
+    ) -> Self {
+        let (direct_events_tx, direct_events) = mpsc::unbounded_channel();
+        let username = clients.insert_random(Client::new(direct_events_tx));
+        tracing::info!("{addr} connected with the name: {username}");
+        let user_events = Framed::new(tcp, LinesCodec::new());
+        let (room, events) = rooms
+            .join(&username, &RoomName::lobby(), None)
+            .expect("the lobby always exists");
+        let mut room_events = StreamMap::new();
+        room_events.insert(room.name().clone(), BroadcastStream::new(events));
+        let joined = HashMap::from([(room.name().clone(), room.clone())]);
+        Self {
+            user_events,
+            server_events,
+            room_events,
+            direct_events,
+            clients,
+            rooms,
+            config,
+            username,
+            addr,
+            state: ConnectionState::Connected,
+            joined,
+            room,
+            capabilities: HashSet::new(),
+        }
+    }
+
+    async fn send_event(&mut self, event: ServerEvent) {
+        tracing::debug!(?event, "Sending event");
+        if let Err(err) = self.user_events.send(event.as_json_str()).await {
+            tracing::error!("Failed to send event: {err}");
+            self.state = ConnectionState::Disconnected;
+        }
+    }
+
+    async fn send_room_info(&mut self) {
+        let info = self.room.info();
+        self.send_event(ServerEvent::room_info(info)).await;
+    }
+
+    async fn send_history(&mut self) {
+        let events = self.room.history();
+        let event = ServerEvent::history(self.room.name(), events);
+        self.send_event(event).await;
+    }
+
+    /// Sends the users of the current room, followed by the bots of its plugins
+    async fn send_users(&mut self) {
+        let users = self
+            .room
+            .list_users()
+            .iter()
+            .chain(&self.rooms.plugins().bots(self.room.name()))
+            .filter_map(|username| self.clients.user_info(username))
+            .collect();
+        let event = ServerEvent::users(self.room.name(), users);
+        self.send_event(event).await;
+    }
+
+    #[instrument(skip(self), fields(addr = %self.addr, username = %self.username))]
+    pub async fn handle(&mut self) {
+        let help = ServerEvent::help(&self.username, commands_help(self.rooms.plugins()));
+        self.send_event(help).await;
+
+        let rooms = self.rooms.list(&self.username);
+        self.send_event(ServerEvent::rooms(rooms)).await;
+
+        self.send_users().await;
+        self.send_room_info().await;
+        self.send_history().await;
+
+        if let Err(err) = self.run().await {
+            tracing::error!("Connection error: {err}");
+        }
+
+        for room in self.joined.values() {
+            self.rooms.leave(&self.username, room);
+        }
+        self.clients.remove(&self.username);
+        tracing::info!("disconnected");
+    }
+
+    async fn run(&mut self) -> anyhow::Result<()> {
+        while self.state == ConnectionState::Connected {
+            let idle_at = self.idle_at();
+            tokio::select! {
+                message = self.user_events.next() => {
+                    let Some(message) = message else {
+                        tracing::info!("Connection closed by the user");
+                        break;
+                    };
+                    let message = message.context("failed to read from stream")?;
+                    if self.clients.touch(&self.username) {
+                        self.broadcast_presence();
+                    }
+                    self.handle_message(message).await;
+                },
+                Some((room_name, event)) = self.room_events.next() => {
+                    match event {
+                        Ok(event) => self.handle_room_event(event).await,
+                        Err(err) => tracing::warn!("Missed events of room {room_name}: {err}"),
+                    }
+                },
+                Some(event) = self.direct_events.recv() => {
+                    self.handle_direct_event(event).await;
+                },
+                event = self.server_events.recv() => {
+                    let event = event.context("failed to read from server events")?;
+                    self.send_event(event).await;
+                },
+                _ = tokio::time::sleep_until(idle_at.unwrap_or_else(Instant::now).into()),
+                    if idle_at.is_some() =>
+                {
+                    self.go_idle();
+                },
+                else => {
+                    tracing::error!("Connection closed");
+                    break;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns when the user will be marked as away if they stay inactive
+    fn idle_at(&self) -> Option<Instant> {
+        let after = self.config.auto_away?;
+        self.clients.idle_at(&self.username, after)
+    }
+
+    fn go_idle(&self) {
+        let Some(after) = self.config.auto_away else {
+            return;
+        };
+        if self.clients.go_idle(&self.username, after) {
+            tracing::info!("Went idle");
+            self.broadcast_presence();
+        }
+    }
+
+    /// Sends the presence of the user to everyone who shares a room with them
+    fn broadcast_presence(&self) {
+        let Some(info) = self.clients.user_info(&self.username) else {
+            return;
+        };
+        let users: HashSet<Username> = self
+            .joined
+            .values()
+            .flat_map(|room| room.list_users())
+            .collect();
+        for username in users {
+            self.clients
+                .send(&username, ServerEvent::presence(info.clone()));
+        }
+    }
+
+    async fn handle_room_event(&mut self, event: ServerEvent) {
+        if let ServerEvent::RoomEvent {
+            username,
+            event: room_event,
+            ..
+        } = &event
+        {
+            let capability = room_event.capability();
+            let supported = capability.is_none_or(|capability| {
+                self.capabilities.contains(&capability) && *username != self.username
+            });
+            if !supported {
+                return;
+            }
+        }
+        if self.removed_from(&event).is_some() {
+            // The user is told about it through their direct events, which cannot miss it
+            return;
+        }
+        self.send_event(event).await;
+    }
+
+    /// Sends an event that is addressed to the user alone
+    ///
+    /// If it says that the user was kicked or banned from a room, the user leaves that room.
+    async fn handle_direct_event(&mut self, event: ServerEvent) {
+        let removed_from = self.removed_from(&event);
+        self.send_event(event).await;
+        if let Some(room_name) = removed_from {
+            self.handle_removal(&room_name).await;
+        }
+    }
+
+    /// Returns the room that the event removes the user from, if it is a kick or ban of them
+    fn removed_from(&self, event: &ServerEvent) -> Option<RoomName> {
+        match event {
+            ServerEvent::RoomEvent {
+                room_name,
+                event: RoomEvent::Kick { username, .. } | RoomEvent::Ban { username, .. },
+                ..
+            } if *username == self.username => Some(room_name.clone()),
+            _ => None,
+        }
+    }
+
+    /// Leaves a room that the user was removed from, moving them to the lobby if it was their
+    /// last room or disconnecting them if it was the lobby
+    async fn handle_removal(&mut self, room_name: &RoomName) {
+        if !self.joined.contains_key(room_name) {
+            return;
+        }
+        tracing::info!("Removed from room {room_name}");
+        if self.joined.len() > 1 {
+            self.leave_room(room_name).await;
+        } else if room_name == &RoomName::lobby() {
+            self.send_event(ServerEvent::Disconnect).await;
+            self.state = ConnectionState::Disconnected;
+        } else {
+            self.join_room(&RoomName::lobby(), None).await;
+            self.leave_room(room_name).await;
+        }
+    }
+
+    /// Joins the room and focuses it, or only focuses it if the user is already in it
+    async fn join_room(&mut self, room_name: &RoomName, key: Option<&str>) {
+        if self.joined.contains_key(room_name) {
+            self.focus_room(room_name).await;
+            return;
+        }
+        match self.rooms.join(&self.username, room_name, key) {
+            Ok((room, events)) => {
+                self.room_events
+                    .insert(room_name.clone(), BroadcastStream::new(events));
+                self.joined.insert(room_name.clone(), room);
+                self.focus_room(room_name).await;
+                self.send_history().await;
+            }
+            Err(err) => {
+                self.send_event(ServerEvent::error(&err.to_string())).await;
+            }
+        }
+    }
+
+    /// Leaves the room, moving the focus elsewhere if it was focused
+    async fn leave_room(&mut self, room_name: &RoomName) {
+        self.room_events.remove(room_name);
+        let Some(room) = self.joined.remove(room_name) else {
+            return;
+        };
+        self.rooms.leave(&self.username, &room);
+        if self.room.name() != room_name {
+            return;
+        }
+        let lobby = RoomName::lobby();
+        let next = if self.joined.contains_key(&lobby) {
+            Some(lobby)
+        } else {
+            self.joined.keys().min().cloned()
+        };
+        if let Some(next) = next {
+            self.focus_room(&next).await;
+        }
+    }
+
+    async fn focus_room(&mut self, room_name: &RoomName) {
+        let Some(room) = self.joined.get(room_name) else {
+            let message = format!("You are not in {room_name}");
+            self.send_event(ServerEvent::error(&message)).await;
+            return;
+        };
+        self.room = room.clone();
+        self.send_event(ServerEvent::focus(room_name)).await;
+        self.send_users().await;
+        self.send_room_info().await;
+    }
+
+    /// Returns whether the user may moderate the given room
+    fn can_moderate(&self, room: &Room) -> bool {
+        room.is_op(&self.username) || self.rooms.is_admin(&self.username)
+    }
+
+    /// Returns the description of a built-in or plugin command
+    fn command_info(&self, name: &str) -> Option<CommandInfo> {
+        match CommandSpec::find(name) {
+            Some(spec) => Some(spec.info()),
+            None => self
+                .rooms
+                .plugins()
+                .commands_help()
+                .into_iter()
+                .find(|command| command.name == name),
+        }
+    }
+
+    /// Runs a command that one of the plugins adds, if it is enabled in the current room
+    async fn handle_plugin_command(&mut self, line: &str) {
+        let (name, args) = match split_command(line) {
+            Ok(command) => command,
+            Err(err) => {
+                self.send_event(ServerEvent::error(&err.to_string())).await;
+                return;
+            }
+        };
+        let name = name.as_str();
+        let Some(plugin) = self.rooms.plugins().command(name) else {
+            return;
+        };
+        if !self
+            .config
+            .is_plugin_enabled(plugin.name(), self.room.name())
+        {
+            let message = format!("/{name} is not enabled in {}", self.room);
+            self.send_event(ServerEvent::error(&message)).await;
+            return;
+        }
+        if self.room.is_muted(&self.username) {
+            let err = MessageError::Muted(self.room.name().clone());
+            self.send_event(ServerEvent::error(&err.to_string())).await;
+            return;
+        }
+        tracing::info!("Received plugin command: /{name} {args}");
+        let bot = Bot::new(Username::from(plugin.name()), self.room.clone());
+        let username = self.username.clone();
+        let command = name.to_string();
+        let result = tokio::task::spawn_blocking(move || {
+            plugin.on_command(&bot, &username, &command, &args)
+        })
+        .await
+        .unwrap_or_else(|err| {
+            tracing::error!("Plugin command /{name} failed: {err}");
+            Err(format!("/{name} failed"))
+        });
+        if let Err(err) = result {
+            self.send_event(ServerEvent::error(&err)).await;
+        }
+    }
+
+    /// Suggests how to finish a partial input line from the current state of the server
+    ///
+    /// Operator commands are only suggested to users who may run them in the current room.
+    fn complete(&self, line: &str) -> ServerEvent {
+        let completion = Completion::new(line);
+        let options: Vec<String> = match completion.target {
+            Some(CompletionTarget::Command) => {
+                let can_moderate = self.can_moderate(&self.room);
+                let mut names: Vec<String> = CommandSpec::all()
+                    .iter()
+                    .filter(|spec| spec.permission == Permission::Anyone || can_moderate)
+                    .flat_map(|spec| iter::once(spec.name).chain(spec.aliases.iter().copied()))
+                    .map(str::to_string)
+                    .collect();
+                let plugins = self.rooms.plugins().enabled(self.room.name());
+                names.extend(
+                    plugins
+                        .iter()
+                        .flat_map(|plugin| plugin.commands())
+                        .map(|command| command.name),
+                );
+                names.iter().map(|name| format!("/{name}")).collect()
+            }
+            Some(CompletionTarget::Arg(ArgKind::Command)) => commands_help(self.rooms.plugins())
+                .into_iter()
+                .map(|command| command.name)
+                .collect(),
+            Some(CompletionTarget::Arg(ArgKind::Room)) => self
+                .rooms
+                .list(&self.username)
+                .into_iter()
+                .map(|room| room.name.to_string())
+                .collect(),
+            Some(CompletionTarget::Arg(ArgKind::User)) => self
+                .clients
+                .usernames()
+                .iter()
+                .map(Username::to_string)
+                .collect(),
+            Some(CompletionTarget::Arg(ArgKind::Toggle)) => {
+                vec!["on".to_string(), "off".to_string()]
+            }
+            Some(CompletionTarget::Arg(ArgKind::Capability)) => Capability::names()
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            Some(CompletionTarget::Arg(ArgKind::Member) | CompletionTarget::Mention) => self
+                .room
+                .list_users()
+                .iter()
+                .map(Username::to_string)
+                .collect(),
+            Some(CompletionTarget::Arg(_)) | None => Vec::new(),
+        };
+        let candidates = completion.candidates(options);
+        ServerEvent::completions(line, completion.start, candidates)
+    }
+
+    /// Returns whether the user may moderate the current room, telling them if they may not
+    ///
+    /// This guards every command that the registry declares as [`Permission::Operator`], and
+    /// setting the topic, which anyone may show.
+    async fn ensure_op(&mut self) -> bool {
+        let is_op = self.can_moderate(&self.room);
+        if !is_op {
+            let message = format!("You are not an operator of {}", self.room);
+            self.send_event(ServerEvent::error(&message)).await;
+        }
+        is_op
+    }
+
+    /// Returns whether the target is in the current room, telling the user if they are not
+    async fn ensure_in_room(&mut self, target: &Username) -> bool {
+        let in_room = self.room.contains(target);
+        if !in_room {
+            self.send_event(ServerEvent::error("user not found")).await;
+        }
+        in_room
+    }
+
+    /// Returns the room among the joined ones that has the message in its history
+    fn room_with_message(&self, id: MessageId) -> Option<Room> {
+        self.joined
+            .values()
+            .find(|room| room.has_message(id))
+            .cloned()
+    }
+
+    async fn react(&mut self, id: MessageId, emoji: &str, add: bool) {
+        let result = match self.room_with_message(id) {
+            Some(room) => room.react(&self.username, id, emoji, add),
+            None => Err(MessageError::NotFound(id)),
+        };
+        if let Err(err) = result {
+            self.send_event(ServerEvent::error(&err.to_string())).await;
+        }
+    }
+
+    async fn invite(&mut self, target: &Username) {
+        if self.room.contains(target) {
+            let message = format!("{target} is already in {}", self.room);
+            self.send_event(ServerEvent::error(&message)).await;
+        } else if self.clients.contains(target) {
+            self.room.invite(target);
+            let invite = ServerEvent::invite(self.room.name(), &self.username);
+            self.clients.send(target, invite);
+        } else {
+            self.send_event(ServerEvent::error("user not found")).await;
+        }
+    }
+
+    async fn handle_message(&mut self, message: String) {
+        if !message.starts_with("/") {
+            tracing::info!("Received message: {:?}", message);
+            if let Err(err) = self.room.send_message(&self.username, &message) {
+                self.send_event(ServerEvent::error(&err.to_string())).await;
+            }
+            return;
+        }
+        match Command::try_from(message.clone()) {
+            Ok(command) => {
+                self.log_command(&command);
+                self.handle_command(command).await
+            }
+            Err(CommandParseError::UnknownCommand(name))
+                if self
+                    .rooms
+                    .plugins()
+                    .command(name.trim_start_matches('/'))
+                    .is_some() =>
+            {
+                self.handle_plugin_command(&message).await
+            }
+            Err(err) => {
+                tracing::error!("Invalid command: {err}");
+                let event = ServerEvent::error(&format!("{err}, try /help"));
+                self.send_event(event).await;
+            }
+        }
+    }
+
+    fn log_command(&self, command: &Command) {
+        match command {
+            Command::SendFile(filename, contents) => {
+                tracing::info!("Received file: {filename}");
+                tracing::trace!("Received file contents: {contents}");
+            }
+            Command::Login(name, _) => tracing::info!("Received login as {name}"),
+            _ => tracing::info!("Received command: {command:?}"),
+        }
+    }
+
+    /// Moves the user to the new name in the rooms they are in, once it was taken in the registry
+    async fn rename(&mut self, new_name: Username, renamed: bool) {
+        if !renamed {
+            let message = format!("{new_name} is already taken");
+            self.send_event(ServerEvent::error(&message)).await;
+            return;
+        }
+        for room in self.joined.values() {
+            room.change_user_name(&self.username, &new_name);
+        }
+        self.username = new_name;
+    }
+
+    /// Runs a parsed command on behalf of the user
+    ///
+    /// The permission comes from the registry and is checked once here, then the command is run
+    /// by the handler that the [`handlers`] table has for its entry in the registry.
+    async fn handle_command(&mut self, command: Command) {
+        let spec = command.spec();
+        if spec.permission == Permission::Operator && !self.ensure_op().await {
+            return;
+        }
+        let handle = handlers::find(spec).expect("every command in the registry has a handler");
+        handle(self, command).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        sync::broadcast,
+        task::JoinHandle,
+    };
+
+    use common::Presence;
+
+    use super::*;
+    use crate::{plugins::Plugins, webhooks::Webhooks};
+
+    /// Connects a user to a new server and returns their socket, name and connection task
+    async fn connect(clients: &Clients, rooms: &Rooms) -> (TcpStream, Username, JoinHandle<()>) {
+        connect_with(clients, rooms, Arc::new(Config::default())).await
+    }
+
+    async fn connect_with(
+        clients: &Clients,
+        rooms: &Rooms,
+        config: Arc<Config>,
+    ) -> (TcpStream, Username, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let user = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+        let (events, _) = broadcast::channel(16);
+        let mut connection = Connection::new(
+            stream,
+            events.subscribe(),
+            clients.clone(),
+            rooms.clone(),
+            config,
+            addr,
+        );
+        let username = connection.username.clone();
+        let task = tokio::spawn(async move {
+            // Keep the server events open for as long as the connection runs
+            let _events = events;
+            connection.handle().await
+        });
+        (user, username, task)
+    }
+
+    async fn rooms(clients: &Clients) -> Rooms {
+        rooms_with(clients, Arc::new(Config::default())).await
+    }
+
+    async fn rooms_with(clients: &Clients, config: Arc<Config>) -> Rooms {
+        let (events, _) = broadcast::channel(16);
+        let plugins = Plugins::new(config.clone(), clients.clone()).await;
+        let webhooks = Webhooks::new(config.clone());
+        Rooms::new(events, config, clients.clone(), plugins, webhooks)
+    }
+
+    async fn send(user: &mut BufReader<TcpStream>, command: Command) {
+        let line = format!("{command}\n");
+        user.write_all(line.as_bytes()).await.unwrap();
+    }
+
+    /// Reads events until one of them is picked, skipping the others
+    async fn until<T>(
+        user: &mut BufReader<TcpStream>,
+        mut pick: impl FnMut(ServerEvent) -> Option<T>,
+    ) -> T {
+        let mut event = String::new();
+        loop {
+            event.clear();
+            user.read_line(&mut event).await.unwrap();
+            if let Some(picked) = ServerEvent::from_json_str(&event).ok().and_then(&mut pick) {
+                return picked;
+            }
+        }
+    }
+
+    async fn error(user: &mut BufReader<TcpStream>) -> String {
+        until(user, |event| match event {
+            ServerEvent::Error(error) => Some(error),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Asks for the completions of a partial line and returns the candidates
+    async fn complete(user: &mut BufReader<TcpStream>, line: &str) -> Vec<String> {
+        send(user, Command::Complete(line.to_string())).await;
+        until(user, |event| match event {
+            ServerEvent::Completions(completions) => Some(completions.candidates),
+            _ => None,
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn connecting_registers_the_name() {
+        let clients = Clients::default();
+        let rooms = rooms(&clients).await;
+        let (_user, username, _task) = connect(&clients, &rooms).await;
+        assert!(clients.contains(&username));
+        assert!(rooms.get(&RoomName::lobby()).unwrap().contains(&username));
+    }
+
+    #[tokio::test]
+    async fn quitting_removes_the_user() {
+        let clients = Clients::default();
+        let rooms = rooms(&clients).await;
+        let (mut user, username, task) = connect(&clients, &rooms).await;
+        user.write_all(b"/quit\n").await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!clients.contains(&username));
+        assert!(!rooms.get(&RoomName::lobby()).unwrap().contains(&username));
+    }
+
+    #[tokio::test]
+    async fn disconnecting_removes_the_user() {
+        let clients = Clients::default();
+        let rooms = rooms(&clients).await;
+        let (user, username, task) = connect(&clients, &rooms).await;
+        drop(user);
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!clients.contains(&username));
+        assert!(!rooms.get(&RoomName::lobby()).unwrap().contains(&username));
+    }
+
+    #[tokio::test]
+    async fn only_members_of_the_room_are_completed_for_moderation() {
+        let clients = Clients::default();
+        let rooms = rooms(&clients).await;
+        let (user, username, _task) = connect(&clients, &rooms).await;
+        let outsider = Username::from("outsider");
+        clients.insert(&outsider, Client::new(mpsc::unbounded_channel().0));
+        let mut user = BufReader::new(user);
+
+        for line in ["/kick ", "/op ", "/deop ", "/mute "] {
+            assert_eq!(complete(&mut user, line).await, vec![username.to_string()]);
+        }
+        let mut completed = complete(&mut user, "/nudge ").await;
+        let mut everyone = vec![outsider.to_string(), username.to_string()];
+        completed.sort();
+        everyone.sort();
+        assert_eq!(completed, everyone);
+    }
+
+    #[tokio::test]
+    async fn help_describes_a_single_command() {
+        let clients = Clients::default();
+        let rooms = rooms(&clients).await;
+        let (user, _, _task) = connect(&clients, &rooms).await;
+        let mut user = BufReader::new(user);
+        let info = |event| match event {
+            ServerEvent::CommandInfo(info) => Some(info),
+            _ => None,
+        };
+
+        for name in ["join", "/join", "j"] {
+            send(&mut user, Command::Help(Some(name.to_string()))).await;
+            let join = until(&mut user, info).await;
+            assert_eq!(join.name, "join");
+            assert_eq!(join.aliases, ["j"]);
+            assert_eq!(join.usage, "/join {room} [key]");
+            assert!(!join.examples.is_empty());
+        }
+        send(&mut user, Command::Help(Some("nope".to_string()))).await;
+        assert_eq!(error(&mut user).await, "Unknown command: /nope");
+    }
+
+    #[tokio::test]
+    async fn nobody_is_kicked_or_banned_from_the_lobby() {
+        let config = Arc::new(Config {
+            admins: HashSet::from([Username::from("root")]),
+            admin_secret: Some("hunter2".parse().unwrap()),
+            ..Config::default()
+        });
+        let clients = Clients::default();
+        let rooms = rooms_with(&clients, config.clone()).await;
+        let (admin, _, _admin_task) = connect_with(&clients, &rooms, config.clone()).await;
+        let (_target, target, _target_task) = connect_with(&clients, &rooms, config).await;
+        let mut admin = BufReader::new(admin);
+        send(&mut admin, Command::Login("root".into(), "hunter2".into())).await;
+
+        send(&mut admin, Command::Kick(target.clone(), None)).await;
+        assert_eq!(
+            error(&mut admin).await,
+            "Users cannot be kicked from the lobby"
+        );
+        send(&mut admin, Command::Ban(target.clone(), None)).await;
+        assert_eq!(
+            error(&mut admin).await,
+            "Users cannot be banned from the lobby"
+        );
+        assert!(rooms.get(&RoomName::lobby()).unwrap().contains(&target));
+    }
+
+    #[tokio::test]
+    async fn only_clients_with_the_capability_see_others_typing() {
+        let clients = Clients::default();
+        let rooms = rooms(&clients).await;
+        let mut users = Vec::new();
+        for capable in [true, true, false] {
+            let (user, _, task) = connect(&clients, &rooms).await;
+            let mut user = BufReader::new(user);
+            if capable {
+                send(&mut user, Command::Capability(Capability::Typing)).await;
+                // The answer to a later command means that the capability was taken into account
+                send(&mut user, Command::Help(Some("cap".to_string()))).await;
+                until(&mut user, |event| match event {
+                    ServerEvent::CommandInfo(_) => Some(()),
+                    _ => None,
+                })
+                .await;
+            }
+            users.push((user, task));
+        }
+
+        let typist = &mut users[0].0;
+        send(typist, Command::Typing).await;
+        typist.write_all(b"done\n").await.unwrap();
+        let mut saw_typing = Vec::new();
+        for (user, _) in &mut users {
+            let mut typing = false;
+            until(user, |event| match event {
+                ServerEvent::RoomEvent { event, .. } => match event {
+                    RoomEvent::Message(_) => Some(()),
+                    RoomEvent::Typing { .. } | RoomEvent::TypingStopped { .. } => {
+                        typing = true;
+                        None
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .await;
+            saw_typing.push(typing);
+        }
+        assert_eq!(saw_typing, [false, true, false]);
+    }
+
+    #[tokio::test]
+    async fn idle_users_are_shown_away_until_they_speak() {
+        let config = Arc::new(Config {
+            auto_away: Some(Duration::from_millis(100)),
+            ..Config::default()
+        });
+        let clients = Clients::default();
+        let rooms = rooms_with(&clients, config.clone()).await;
+        let (idler, idle, _idler_task) = connect_with(&clients, &rooms, config.clone()).await;
+        let (observer, _, _observer_task) = connect_with(&clients, &rooms, config).await;
+        let mut idler = BufReader::new(idler);
+        let mut observer = BufReader::new(observer);
+        let mut presence = async || {
+            until(&mut observer, |event| match event {
+                ServerEvent::Presence(info) if info.username == idle => Some(info.presence),
+                _ => None,
+            })
+            .await
+        };
+
+        assert_eq!(presence().await, Presence::Away);
+        send(&mut idler, Command::Help(None)).await;
+        assert_eq!(presence().await, Presence::Online);
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use common::{Message, MessageId, RoomEvent, SearchQuery, ServerEvent, Username};
+
+use crate::{clients::ClientId, search::SearchIndex};
+
+/// The source of message IDs, shared by all rooms so that IDs are unique server-wide
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The most recent messages of a room, oldest first
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    inner: Arc<RwLock<VecDeque<ServerEvent>>>,
+    /// The connected users who reacted to each message
+    ///
+    /// Like the authors, they are kept by ID so that a user who changes their name cannot react
+    /// with the same emoji twice. This lock is only ever taken while holding the lock of the messages.
+    reactions: Arc<RwLock<HashMap<MessageId, Reactions>>>,
+    /// The connected users who sent the messages, who may edit and delete them
+    ///
+    /// Authors are kept by ID rather than by name, so that they keep their messages when they
+    /// change their name and nobody else gets them by taking that name. Like the reactions, this
+    /// lock is only ever taken while holding the lock of the messages.
+    authors: Arc<RwLock<HashMap<MessageId, ClientId>>>,
+    /// The words of the messages, for searching
+    ///
+    /// Like the reactions, this lock is only ever taken while holding the lock of the messages.
+    index: Arc<RwLock<SearchIndex>>,
+}
+
+/// The clients of the users who reacted to a message, by emoji
+type Reactions = HashMap<String, HashSet<ClientId>>;
+
+impl History {
+    /// The maximum number of messages that are kept
+    pub(crate) const CAPACITY: usize = 256;
+
+    /// Returns a new message ID
+    pub fn next_id() -> MessageId {
+        MessageId::new(NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Appends a message event, dropping the oldest message if the history is full
+    ///
+    /// The client is the connected user who sent the message, `None` for bots. The callback runs
+    /// while the history is locked, which keeps concurrent messages in the same order in the
+    /// history as in the room.
+    pub fn push(
+        &self,
+        event: ServerEvent,
+        client: Option<ClientId>,
+        then: impl FnOnce(ServerEvent),
+    ) {
+        let mut inner = self.inner.write().unwrap();
+        let mut authors = self.authors.write().unwrap();
+        let mut index = self.index.write().unwrap();
+        if inner.len() == Self::CAPACITY {
+            if let Some((_, oldest)) = inner.pop_front().as_ref().and_then(message) {
+                self.reactions.write().unwrap().remove(&oldest.id);
+                authors.remove(&oldest.id);
+                index.remove(oldest.id);
+            }
+        }
+        if let Some((author, message)) = message(&event) {
+            index.insert(message.id, author, &message.text);
+            if let Some(client) = client {
+                authors.insert(message.id, client);
+            }
+        }
+        inner.push_back(event.clone());
+        then(event);
+    }
+
+    pub fn contains(&self, id: MessageId) -> bool {
+        self.inner
+            .read()
+            .unwrap()
+            .iter()
+            .any(|event| message(event).is_some_and(|(_, message)| message.id == id))
+    }
+
+    /// Returns the ID of the most recent message
+    pub fn latest_id(&self) -> Option<MessageId> {
+        self.inner
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .find_map(|event| message(event).map(|(_, message)| message.id))
+    }
+
+    /// Counts the messages of others after the given one, and how many of them mention the user
+    pub fn unread(&self, username: &Username, after: Option<MessageId>) -> (usize, usize) {
+        self.inner
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(message)
+            .filter(|(author, message)| *author != username && Some(message.id) > after)
+            .fold((0, 0), |(unread, mentions), (_, message)| {
+                (
+                    unread + 1,
+                    mentions + usize::from(message.mentions(username)),
+                )
+            })
+    }
+
+    /// Returns whether the message was sent by the given client
+    pub fn is_author(&self, id: MessageId, client: ClientId) -> bool {
+        let _messages = self.inner.read().unwrap();
+        self.authors.read().unwrap().get(&id) == Some(&client)
+    }
+
+    /// Replaces the text of the message, returning `false` if it is not in the history
+    pub fn edit(&self, id: MessageId, text: &str) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        let Some(message) = message_mut(&mut inner, id) else {
+            return false;
+        };
+        message.text = text.to_string();
+        message.edited = true;
+        self.index.write().unwrap().update(id, text);
+        true
+    }
+
+    /// Adds or removes the reaction of the user to the message
+    ///
+    /// Returns how many users reacted to the message with that emoji afterwards, or `None` if
+    /// the message is not in the history.
+    pub fn react(&self, id: MessageId, emoji: &str, client: ClientId, add: bool) -> Option<usize> {
+        let mut inner = self.inner.write().unwrap();
+        let message = message_mut(&mut inner, id)?;
+        let mut reactions = self.reactions.write().unwrap();
+        let by_emoji = reactions.entry(id).or_default();
+        let users = by_emoji.entry(emoji.to_string()).or_default();
+        if add {
+            users.insert(client);
+        } else {
+            users.remove(&client);
+        }
+        let count = users.len();
+        if count == 0 {
+            by_emoji.remove(emoji);
+            message.reactions.remove(emoji);
+        } else {
+            message.reactions.insert(emoji.to_string(), count);
+        }
+        if by_emoji.is_empty() {
+            reactions.remove(&id);
+        }
+        Some(count)
+    }
+
+    /// Removes the message, returning `false` if it is not in the history
+    pub fn remove(&self, id: MessageId) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        let len = inner.len();
+        inner.retain(|event| message(event).is_none_or(|(_, message)| message.id != id));
+        self.reactions.write().unwrap().remove(&id);
+        self.authors.write().unwrap().remove(&id);
+        self.index.write().unwrap().remove(id);
+        inner.len() != len
+    }
+
+    /// Returns the root of the thread that the message belongs to
+    ///
+    /// The chain of replies is followed as far as the history goes back.
+    pub fn thread_root(&self, id: MessageId) -> MessageId {
+        let inner = self.inner.read().unwrap();
+        let mut root = id;
+        while let Some(parent) = inner.iter().find_map(|event| {
+            message(event)
+                .filter(|(_, message)| message.id == root)
+                .and_then(|(_, message)| message.reply_to)
+        }) {
+            root = parent;
+        }
+        root
+    }
+
+    /// Returns the root message and all direct and indirect replies to it
+    pub fn thread(&self, root: MessageId) -> Vec<ServerEvent> {
+        let mut ids = HashSet::from([root]);
+        self.inner
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|event| {
+                let Some((_, message)) = message(event) else {
+                    return false;
+                };
+                let in_thread = message.id == root
+                    || message.reply_to.is_some_and(|parent| ids.contains(&parent));
+                if in_thread {
+                    ids.insert(message.id);
+                }
+                in_thread
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the IDs of the messages that match the query
+    pub fn search(&self, query: &SearchQuery) -> Vec<MessageId> {
+        let _messages = self.inner.read().unwrap();
+        self.index.read().unwrap().search(query)
+    }
+
+    /// Returns the message along with the messages right before and after it
+    pub fn context(&self, id: MessageId) -> Vec<ServerEvent> {
+        let inner = self.inner.read().unwrap();
+        let Some(position) = inner
+            .iter()
+            .position(|event| message(event).is_some_and(|(_, message)| message.id == id))
+        else {
+            return Vec::new();
+        };
+        let start = position.saturating_sub(1);
+        let end = (position + 2).min(inner.len());
+        inner.range(start..end).cloned().collect()
+    }
+
+    /// Returns the message events in the order they were sent
+    pub fn events(&self) -> Vec<ServerEvent> {
+        self.inner.read().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Returns the author and the message of a message event
+fn message(event: &ServerEvent) -> Option<(&Username, &Message)> {
+    match event {
+        ServerEvent::RoomEvent {
+            username,
+            event: RoomEvent::Message(message),
+            ..
+        } => Some((username, message)),
+        _ => None,
+    }
+}
+
+fn message_mut(events: &mut VecDeque<ServerEvent>, id: MessageId) -> Option<&mut Message> {
+    events.iter_mut().find_map(|event| match event {
+        ServerEvent::RoomEvent {
+            event: RoomEvent::Message(message),
+            ..
+        } if message.id == id => Some(message),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use common::RoomName;
+
+    use super::*;
+
+    /// Adds a message to the history, as a reply if a parent is given
+    fn post(history: &History, id: u64, reply_to: Option<u64>) {
+        let id = MessageId::new(id);
+        let event = match reply_to {
+            Some(parent) => RoomEvent::reply(id, "reply", MessageId::new(parent)),
+            None => RoomEvent::message(id, "message"),
+        };
+        let event = ServerEvent::room_event(&RoomName::lobby(), &Username::from("alice"), event);
+        history.push(event, None, |_| {});
+    }
+
+    /// Returns the root of the thread of a message and the IDs of the messages in it
+    fn thread(history: &History, id: u64) -> (u64, Vec<u64>) {
+        let root = history.thread_root(MessageId::new(id));
+        let ids = history
+            .thread(root)
+            .iter()
+            .filter_map(message)
+            .map(|(_, message)| message.id.as_u64())
+            .collect();
+        (root.as_u64(), ids)
+    }
+
+    #[test]
+    fn threads_hold_all_direct_and_indirect_replies() {
+        let history = History::default();
+        post(&history, 1, None);
+        post(&history, 2, Some(1));
+        post(&history, 3, None);
+        post(&history, 4, Some(2));
+        post(&history, 5, Some(1));
+
+        for id in [1, 2, 4, 5] {
+            assert_eq!(thread(&history, id), (1, vec![1, 2, 4, 5]), "{id}");
+        }
+        assert_eq!(thread(&history, 3), (3, vec![3]));
+    }
+
+    #[test]
+    fn threads_outlive_their_root() {
+        let history = History::default();
+        post(&history, 1, None);
+        post(&history, 2, Some(1));
+        for id in 3..=History::CAPACITY as u64 {
+            post(&history, id, None);
+        }
+        // The history is full, so this pushes the root out
+        post(&history, 1000, Some(2));
+
+        assert_eq!(thread(&history, 1000), (1, vec![2, 1000]));
+    }
+}
@@ -3,18 +3,39 @@ use clap::{
     Parser,
 };
 use clap_verbosity_flag::{InfoLevel, Verbosity};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use common::{RoomName, Username};
+use reqwest::Url;
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
 use tracing::level_filters::LevelFilter;
 use tracing_log::AsTrace;
 use tracing_subscriber::EnvFilter;
 
-use self::server::Server;
+use self::{
+    config::{Config, Secret},
+    server::Server,
+};
 
+mod api;
+mod clients;
+mod config;
 mod connection;
+mod history;
+mod plugin;
+mod plugins;
+mod read_markers;
 mod room;
 mod rooms;
+mod sanctions;
+mod search;
 mod server;
+mod typing;
 mod users;
+mod webhooks;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
@@ -22,7 +43,7 @@ async fn main() -> Result<(), anyhow::Error> {
     let level = args.verbosity.log_level_filter().as_trace();
     init_tracing(level);
     tracing::debug!("Starting server with args: {:#?}", args);
-    let server = Server::listen(args.address()).await?;
+    let server = Server::listen(args.address(), args.config()).await?;
     server.run().await;
     Ok(())
 }
@@ -44,6 +65,71 @@ pub struct Args {
     #[arg(short, long, default_value_t = 42069)]
     port: u16,
 
+    /// Room names that only admins are allowed to create
+    #[arg(long = "reserved-room", value_name = "ROOM")]
+    reserved_rooms: Vec<RoomName>,
+
+    /// Usernames with administrative privileges, which can only be taken with /login
+    #[arg(long = "admin", value_name = "USERNAME")]
+    admins: Vec<Username>,
+
+    /// The secret that admins give to /login, without which admin names cannot be taken
+    #[arg(long, value_name = "SECRET")]
+    admin_secret: Option<Secret>,
+
+    /// Rooms that exist from the start and are kept when they become empty
+    #[arg(long = "persistent-room", value_name = "ROOM")]
+    persistent_rooms: Vec<RoomName>,
+
+    /// How long empty rooms are kept around before they are deleted (e.g. "5m")
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    room_grace_period: Option<Duration>,
+
+    /// How long users can be inactive before they are marked as away (e.g. "10m")
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    auto_away: Option<Duration>,
+
+    /// How long users have to wait between two nudges
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "30s",
+        value_parser = humantime::parse_duration
+    )]
+    nudge_cooldown: Duration,
+
+    /// Enables a plugin in a room, e.g. "dice:lobby"
+    #[arg(long = "plugin", value_name = "PLUGIN:ROOM", value_parser = parse_plugin)]
+    plugins: Vec<(String, RoomName)>,
+
+    /// The directory to load scripted plugins from, which is watched for changes
+    #[arg(long, value_name = "DIR")]
+    scripts_dir: Option<PathBuf>,
+
+    /// Posts the events of a room to a URL, e.g. "lobby=https://example.com/hook"
+    #[arg(long = "webhook", value_name = "ROOM=URL", value_parser = parse_webhook)]
+    webhooks: Vec<(RoomName, Url)>,
+
+    /// The key that webhook requests are signed with
+    #[arg(long, value_name = "SECRET")]
+    webhook_secret: Option<Secret>,
+
+    /// The file that webhook events are appended to when they cannot be delivered
+    #[arg(long, value_name = "FILE")]
+    webhook_dead_letters: Option<PathBuf>,
+
+    /// The address to serve the HTTP API on (e.g. "127.0.0.1:8080")
+    #[arg(long, value_name = "ADDRESS")]
+    http_address: Option<SocketAddr>,
+
+    /// A token that grants access to the HTTP API
+    #[arg(long = "api-token", value_name = "TOKEN")]
+    api_tokens: Vec<Secret>,
+
+    /// A bot user that the HTTP API may post as
+    #[arg(long = "api-bot", value_name = "USERNAME")]
+    api_bots: Vec<Username>,
+
     /// Verbosity flags
     ///
     /// Automatically parses one or more --verbose and --quiet flags to set the log level.
@@ -56,6 +142,57 @@ impl Args {
     pub fn address(&self) -> SocketAddr {
         SocketAddr::new(self.ip, self.port)
     }
+
+    pub fn config(&self) -> Config {
+        Config {
+            reserved_rooms: self.reserved_rooms.iter().cloned().collect(),
+            admins: self.admins.iter().cloned().collect(),
+            admin_secret: self.admin_secret.clone(),
+            persistent_rooms: self.persistent_rooms.iter().cloned().collect(),
+            room_grace_period: self.room_grace_period,
+            auto_away: self.auto_away,
+            nudge_cooldown: self.nudge_cooldown,
+            plugins: self.plugins.iter().cloned().fold(
+                HashMap::new(),
+                |mut plugins: HashMap<_, HashSet<_>>, (plugin, room_name)| {
+                    plugins.entry(plugin).or_default().insert(room_name);
+                    plugins
+                },
+            ),
+            scripts_dir: self.scripts_dir.clone(),
+            webhooks: self.webhooks.iter().cloned().fold(
+                HashMap::new(),
+                |mut webhooks: HashMap<_, Vec<_>>, (room_name, url)| {
+                    webhooks.entry(room_name).or_default().push(url);
+                    webhooks
+                },
+            ),
+            webhook_secret: self.webhook_secret.clone(),
+            webhook_dead_letters: self.webhook_dead_letters.clone(),
+            http_address: self.http_address,
+            api_tokens: self.api_tokens.iter().cloned().collect(),
+            api_bots: self.api_bots.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Parses the name of a room and a URL to post its events to, separated by an equals sign
+fn parse_webhook(value: &str) -> Result<(RoomName, Url), String> {
+    let (room_name, url) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected ROOM=URL, got {value}"))?;
+    let room_name = room_name.parse().map_err(|err| format!("{err}"))?;
+    let url = url.parse().map_err(|err| format!("{err}"))?;
+    Ok((room_name, url))
+}
+
+/// Parses the name of a plugin and a room it is enabled in, separated by a colon
+fn parse_plugin(value: &str) -> Result<(String, RoomName), String> {
+    let (plugin, room_name) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected PLUGIN:ROOM, got {value}"))?;
+    let room_name = room_name.parse().map_err(|err| format!("{err}"))?;
+    Ok((plugin.to_string(), room_name))
 }
 
 pub fn init_tracing(level_filter: LevelFilter) {
@@ -0,0 +1,97 @@
+use common::{ArgSpec, CommandInfo, MessageId, Permission, RoomEvent, RoomName, Username};
+
+use crate::room::{MessageError, Room};
+
+/// A helper that runs inside the server, such as a dice roller or a reminder
+///
+/// Each plugin has a bot user named after it, which it uses to post in the rooms that it is
+/// enabled in. Plugins are enabled per room in the configuration. The handlers are called on a
+/// blocking thread, so they may take their time without holding up the server.
+pub trait Plugin: Send + Sync {
+    /// The name of the plugin, which is also the name of its bot user
+    fn name(&self) -> &str;
+
+    /// The slash commands that the plugin adds
+    fn commands(&self) -> Vec<CommandInfo> {
+        Vec::new()
+    }
+
+    /// Called for every event in a room that the plugin is enabled in, except those of bots
+    fn on_event(&self, bot: &Bot, username: &Username, event: &RoomEvent) {
+        let _ = (bot, username, event);
+    }
+
+    /// Called when a user runs one of the commands of the plugin in a room it is enabled in
+    ///
+    /// The arguments are the rest of the line after the command name. The error is shown to the
+    /// user who ran the command.
+    fn on_command(
+        &self,
+        bot: &Bot,
+        username: &Username,
+        command: &str,
+        args: &str,
+    ) -> Result<(), String> {
+        let _ = (bot, username, args);
+        Err(format!("/{command} is not implemented"))
+    }
+}
+
+/// The declaration of a command that a compiled plugin adds
+///
+/// The arguments are only used for the help, plugins parse them themselves.
+#[derive(Debug)]
+pub struct PluginCommand {
+    /// The name of the command, without the leading slash
+    pub name: &'static str,
+    pub args: &'static [ArgSpec],
+    /// A one-line description of what the command does
+    pub help: &'static str,
+    pub examples: &'static [&'static str],
+}
+
+impl PluginCommand {
+    /// Returns the description of the command that is sent to clients
+    pub fn info(&self) -> CommandInfo {
+        let mut usage = format!("/{}", self.name);
+        for arg in self.args {
+            usage.push(' ');
+            usage.push_str(&arg.usage());
+        }
+        CommandInfo {
+            name: self.name.to_string(),
+            aliases: Vec::new(),
+            usage,
+            args: self.args.iter().map(ArgSpec::info).collect(),
+            permission: Permission::Anyone,
+            description: self.help.to_string(),
+            examples: self
+                .examples
+                .iter()
+                .map(|example| example.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// The bot user of a plugin in one room
+#[derive(Debug, Clone)]
+pub struct Bot {
+    username: Username,
+    room: Room,
+}
+
+impl Bot {
+    pub fn new(username: Username, room: Room) -> Self {
+        Self { username, room }
+    }
+
+    pub fn room_name(&self) -> &RoomName {
+        self.room.name()
+    }
+
+    /// Posts a message in the room as the bot
+    pub fn say(&self, message: &str) -> Result<MessageId, MessageError> {
+        self.room.send_message(&self.username, message)
+    }
+}
@@ -0,0 +1,75 @@
+use common::{ArgKind, ArgSpec, CommandInfo, Username};
+use rand::Rng;
+
+use crate::plugin::{Bot, Plugin, PluginCommand};
+
+/// Rolls dice written like `2d6`, for games and for picking who goes first
+pub struct Dice;
+
+impl Dice {
+    const MAX_DICE: u32 = 100;
+    const MAX_SIDES: u32 = 1000;
+
+    const COMMANDS: &'static [PluginCommand] = &[PluginCommand {
+        name: "roll",
+        args: &[ArgSpec::optional("dice", ArgKind::Word)],
+        help: "Roll dice, one six-sided die by default",
+        examples: &["/roll", "/roll 2d6", "/roll d20"],
+    }];
+
+    /// Parses dice like `d20` or `3d6`, one six-sided die if nothing is given
+    fn parse(dice: &str) -> Result<(u32, u32), String> {
+        if dice.is_empty() {
+            return Ok((1, 6));
+        }
+        let invalid = || format!("Invalid dice: {dice}, expected something like 2d6");
+        let lowercase = dice.to_lowercase();
+        let (count, sides) = lowercase.split_once('d').ok_or_else(invalid)?;
+        let count = match count {
+            "" => 1,
+            count => count.parse().map_err(|_| invalid())?,
+        };
+        let sides = sides.parse().map_err(|_| invalid())?;
+        if !(1..=Self::MAX_DICE).contains(&count) || !(2..=Self::MAX_SIDES).contains(&sides) {
+            return Err(format!(
+                "Roll between 1 and {} dice with 2 to {} sides",
+                Self::MAX_DICE,
+                Self::MAX_SIDES
+            ));
+        }
+        Ok((count, sides))
+    }
+}
+
+impl Plugin for Dice {
+    fn name(&self) -> &str {
+        "dice"
+    }
+
+    fn commands(&self) -> Vec<CommandInfo> {
+        Self::COMMANDS.iter().map(PluginCommand::info).collect()
+    }
+
+    fn on_command(
+        &self,
+        bot: &Bot,
+        username: &Username,
+        _command: &str,
+        args: &str,
+    ) -> Result<(), String> {
+        let (count, sides) = Self::parse(args)?;
+        let mut rng = rand::thread_rng();
+        let rolls: Vec<u32> = (0..count).map(|_| rng.gen_range(1..=sides)).collect();
+        let total: u32 = rolls.iter().sum();
+        let message = if count == 1 {
+            format!("{username} rolled a d{sides}: {total}")
+        } else {
+            let rolls: Vec<String> = rolls.iter().map(u32::to_string).collect();
+            format!(
+                "{username} rolled {count}d{sides}: {} = {total}",
+                rolls.join(" + ")
+            )
+        };
+        bot.say(&message).map(|_| ()).map_err(|err| err.to_string())
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+use common::{CommandInfo, RoomName, ServerEvent, Username};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    clients::Clients,
+    config::Config,
+    plugin::{Bot, Plugin},
+    room::Room,
+};
+
+mod dice;
+mod scripts;
+
+/// The plugins that are built into the server
+fn builtin() -> Vec<Arc<dyn Plugin>> {
+    vec![Arc::new(dice::Dice)]
+}
+
+/// The plugins of the server and the rooms that they are enabled in
+///
+/// Scripted plugins come and go while the server runs, so the list is read anew for every
+/// event and command.
+#[derive(Clone)]
+pub struct Plugins {
+    plugins: Arc<RwLock<Vec<Arc<dyn Plugin>>>>,
+    config: Arc<Config>,
+    clients: Clients,
+}
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.plugins
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .map(|plugin| plugin.name()),
+            )
+            .finish()
+    }
+}
+
+impl Plugins {
+    /// Loads the built-in plugins and the scripts, and reserves the names of their bot users
+    pub async fn new(config: Arc<Config>, clients: Clients) -> Self {
+        let plugins = Self {
+            plugins: Arc::default(),
+            config: config.clone(),
+            clients,
+        };
+        for plugin in builtin() {
+            plugins.register(plugin);
+        }
+        if let Some(dir) = &config.scripts_dir {
+            scripts::watch(plugins.clone(), dir.clone()).await;
+        }
+        for name in config.plugins.keys() {
+            if !plugins.contains(name) {
+                tracing::warn!("Unknown plugin {name} in the configuration");
+            }
+        }
+        plugins
+    }
+
+    /// Adds a plugin, returning `false` if a plugin or a user already goes by its name
+    pub fn register(&self, plugin: Arc<dyn Plugin>) -> bool {
+        let mut plugins = self.plugins.write().unwrap();
+        if plugins.iter().any(|other| other.name() == plugin.name()) {
+            return false;
+        }
+        if !self.clients.add_bot(&Username::from(plugin.name())) {
+            return false;
+        }
+        tracing::info!("Loaded plugin {}", plugin.name());
+        plugins.push(plugin);
+        true
+    }
+
+    /// Swaps a plugin for a new version of it, or adds it if there is none
+    ///
+    /// The old version is replaced while the list is locked, so the name of the bot user stays
+    /// reserved throughout and no user can take it in between.
+    pub fn replace(&self, plugin: Arc<dyn Plugin>) -> bool {
+        let mut plugins = self.plugins.write().unwrap();
+        match plugins
+            .iter_mut()
+            .find(|other| other.name() == plugin.name())
+        {
+            Some(other) => {
+                tracing::info!("Reloaded plugin {}", plugin.name());
+                *other = plugin;
+                true
+            }
+            None => {
+                drop(plugins);
+                self.register(plugin)
+            }
+        }
+    }
+
+    /// Removes a plugin and releases the name of its bot user
+    pub fn unregister(&self, name: &str) {
+        let mut plugins = self.plugins.write().unwrap();
+        let count = plugins.len();
+        plugins.retain(|plugin| plugin.name() != name);
+        if plugins.len() != count {
+            self.clients.remove_bot(&Username::from(name));
+            tracing::info!("Unloaded plugin {name}");
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.plugins
+            .read()
+            .unwrap()
+            .iter()
+            .any(|plugin| plugin.name() == name)
+    }
+
+    /// Returns the plugins that are enabled in the given room
+    pub fn enabled(&self, room_name: &RoomName) -> Vec<Arc<dyn Plugin>> {
+        self.plugins
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|plugin| self.config.is_plugin_enabled(plugin.name(), room_name))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the bot users of the plugins that are enabled in the given room
+    pub fn bots(&self, room_name: &RoomName) -> Vec<Username> {
+        self.enabled(room_name)
+            .iter()
+            .map(|plugin| Username::from(plugin.name()))
+            .collect()
+    }
+
+    /// Finds the plugin that adds the command with the given name, without the leading slash
+    pub fn command(&self, name: &str) -> Option<Arc<dyn Plugin>> {
+        self.plugins
+            .read()
+            .unwrap()
+            .iter()
+            .find(|plugin| plugin.commands().iter().any(|command| command.name == name))
+            .cloned()
+    }
+
+    /// Returns the description of the commands of all plugins
+    pub fn commands_help(&self) -> Vec<CommandInfo> {
+        self.plugins
+            .read()
+            .unwrap()
+            .iter()
+            .flat_map(|plugin| plugin.commands())
+            .collect()
+    }
+
+    /// Feeds the events of a new room to the plugins that are enabled in it
+    ///
+    /// The events are handled on a task of their own until the room is closed, so that plugins
+    /// can post from their handlers without holding up the room. The handlers run on a blocking
+    /// thread, one event after the other, as scripts may take up to their time limit.
+    pub fn attach(&self, room: &Room) {
+        let configured = self
+            .config
+            .plugins
+            .values()
+            .any(|rooms| rooms.contains(room.name()));
+        if !configured {
+            return;
+        }
+        let plugins = self.clone();
+        let room = room.clone();
+        let mut events = room.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    event = events.recv() => event,
+                    _ = room.closed() => break,
+                };
+                let (username, event) = match event {
+                    Ok(ServerEvent::RoomEvent {
+                        username, event, ..
+                    }) => (username, event),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Plugins of {room} skipped {skipped} events");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if plugins.clients.is_bot(&username) {
+                    continue;
+                }
+                let enabled = plugins.enabled(room.name());
+                let bot_room = room.clone();
+                let handled = tokio::task::spawn_blocking(move || {
+                    for plugin in enabled {
+                        let bot = Bot::new(Username::from(plugin.name()), bot_room.clone());
+                        plugin.on_event(&bot, &username, &event);
+                    }
+                })
+                .await;
+                if let Err(err) = handled {
+                    tracing::error!("Plugins of {room} failed to handle an event: {err}");
+                }
+            }
+            tracing::debug!("Stopped the plugins of {room}");
+        });
+    }
+}
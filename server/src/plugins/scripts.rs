@@ -0,0 +1,486 @@
+//! Plugins written as Rhai scripts, loaded from a directory and reloaded when they change
+//!
+//! Each `<name>.rhai` file is a plugin with a bot user called `<name>`. Scripts define any of
+//! these functions, which are called for the rooms that the plugin is enabled in:
+//!
+//! - `commands()` returns a map from the names of the commands that the script adds to their
+//!   descriptions, e.g. `#{ standup: "Start the daily standup" }`
+//! - `on_command(user, command, args)` runs one of those commands
+//! - `on_message(user, text)`, `on_join(user)` and `on_leave(user)` react to room events
+//!
+//! Scripts can call `say(text)` to post as their bot and `room()` to get the name of the room.
+//! They cannot import modules or evaluate code, and every call is cut off after a number of
+//! operations and a time limit.
+
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use common::{ArgInfo, ArgKind, CommandInfo, CommandSpec, Permission, RoomEvent, Username};
+use rhai::{
+    module_resolvers::DummyModuleResolver, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs,
+    Map, NativeCallContext, Scope, AST,
+};
+
+use crate::plugin::{Bot, Plugin};
+
+use super::Plugins;
+
+/// How often the directory is checked for new, changed and removed scripts
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+/// How long a single call into a script may take
+const TIME_LIMIT: Duration = Duration::from_millis(100);
+/// How many operations a single call into a script may take
+const MAX_OPERATIONS: u64 = 100_000;
+/// How many messages a single call into a script may send
+const MAX_MESSAGES: usize = 5;
+
+thread_local! {
+    /// When the script that runs on this thread has to stop
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Creates the engine that all scripts run on, with the API and limits of scripts
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(10_000)
+        .set_max_array_size(1_000)
+        .set_max_map_size(1_000)
+        .disable_symbol("eval");
+    engine.on_progress(|operations| {
+        // Reading the clock on every operation would slow scripts down for no good reason
+        if operations % 1024 != 0 {
+            return None;
+        }
+        let expired = DEADLINE
+            .get()
+            .is_some_and(|deadline| Instant::now() >= deadline);
+        expired.then(|| Dynamic::from("time limit exceeded"))
+    });
+    engine.on_print(|text| tracing::info!("Script printed: {text}"));
+    engine.on_debug(|text, source, position| {
+        tracing::debug!("Script {source:?} at {position}: {text}");
+    });
+    engine.register_fn(
+        "say",
+        |context: NativeCallContext, message: &str| -> Result<(), Box<EvalAltResult>> {
+            Context::of(&context)?.say(message)
+        },
+    );
+    engine.register_fn(
+        "room",
+        |context: NativeCallContext| -> Result<String, Box<EvalAltResult>> {
+            Ok(Context::of(&context)?.room_name)
+        },
+    );
+    engine
+}
+
+/// The room that a call into a script is made for, and what the script said there
+#[derive(Debug, Clone, Default)]
+struct Context {
+    room_name: String,
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl Context {
+    fn new(room_name: &str) -> Self {
+        Self {
+            room_name: room_name.to_string(),
+            messages: Arc::default(),
+        }
+    }
+
+    /// Returns the context of the call that a native function was called from
+    fn of(context: &NativeCallContext) -> Result<Self, Box<EvalAltResult>> {
+        context
+            .tag()
+            .and_then(|tag| tag.clone().try_cast::<Context>())
+            .ok_or_else(|| "not available outside of a call from the server".into())
+    }
+
+    fn say(&self, message: &str) -> Result<(), Box<EvalAltResult>> {
+        let mut messages = self.messages.lock().unwrap();
+        if messages.len() >= MAX_MESSAGES {
+            return Err(format!("cannot say more than {MAX_MESSAGES} messages at once").into());
+        }
+        messages.push(message.to_string());
+        Ok(())
+    }
+
+    fn take_messages(&self) -> Vec<String> {
+        std::mem::take(&mut self.messages.lock().unwrap())
+    }
+}
+
+/// A plugin that is loaded from a script
+pub struct Script {
+    name: String,
+    engine: Arc<Engine>,
+    ast: AST,
+    commands: Vec<CommandInfo>,
+}
+
+impl Script {
+    fn load(engine: &Arc<Engine>, name: &str, path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let ast = engine.compile(source).map_err(|err| err.to_string())?;
+        let mut script = Self {
+            name: name.to_string(),
+            engine: engine.clone(),
+            ast,
+            commands: Vec::new(),
+        };
+        if script.has_hook("commands") {
+            let commands: Map = script.call(&Context::default(), "commands", ())?;
+            script.commands = commands
+                .into_iter()
+                .map(|(name, help)| Self::command_info(&name, help))
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(script)
+    }
+
+    /// Returns the description of a command that the script adds
+    fn command_info(name: &str, help: Dynamic) -> Result<CommandInfo, String> {
+        let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric());
+        if !valid {
+            return Err(format!("invalid command name: {name}"));
+        }
+        if CommandSpec::find(name).is_some() {
+            return Err(format!("/{name} is a built-in command"));
+        }
+        Ok(CommandInfo {
+            name: name.to_string(),
+            aliases: Vec::new(),
+            usage: format!("/{name} [args]"),
+            args: vec![ArgInfo {
+                name: "args".to_string(),
+                kind: ArgKind::Text,
+                required: false,
+            }],
+            permission: Permission::Anyone,
+            description: help.to_string(),
+            examples: Vec::new(),
+        })
+    }
+
+    fn has_hook(&self, hook: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == hook)
+    }
+
+    /// Calls a function of the script within the limits of scripts
+    fn call<T: Clone + Send + Sync + 'static>(
+        &self,
+        context: &Context,
+        hook: &str,
+        args: impl FuncArgs,
+    ) -> Result<T, String> {
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .with_tag(context.clone());
+        DEADLINE.set(Some(Instant::now() + TIME_LIMIT));
+        let result =
+            self.engine
+                .call_fn_with_options(options, &mut Scope::new(), &self.ast, hook, args);
+        DEADLINE.set(None);
+        result.map_err(|err| format!("{hook} of script {} failed: {err}", self.name))
+    }
+
+    /// Calls a hook if the script defines it and posts what it said as the bot
+    fn run(&self, bot: &Bot, hook: &str, args: impl FuncArgs) -> Result<(), String> {
+        if !self.has_hook(hook) {
+            return Ok(());
+        }
+        let context = Context::new(&bot.room_name().to_string());
+        let result = self.call::<Dynamic>(&context, hook, args);
+        for message in context.take_messages() {
+            bot.say(&message).map_err(|err| err.to_string())?;
+        }
+        result.map(|_| ())
+    }
+}
+
+impl Plugin for Script {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn commands(&self) -> Vec<CommandInfo> {
+        self.commands.clone()
+    }
+
+    fn on_event(&self, bot: &Bot, username: &Username, event: &RoomEvent) {
+        let user = username.to_string();
+        let result = match event {
+            RoomEvent::Message(message) => {
+                self.run(bot, "on_message", (user, message.text.clone()))
+            }
+            RoomEvent::Joined(_) => self.run(bot, "on_join", (user,)),
+            RoomEvent::Left(_) => self.run(bot, "on_leave", (user,)),
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            tracing::warn!("{err}");
+        }
+    }
+
+    fn on_command(
+        &self,
+        bot: &Bot,
+        username: &Username,
+        command: &str,
+        args: &str,
+    ) -> Result<(), String> {
+        let args = (username.to_string(), command.to_string(), args.to_string());
+        self.run(bot, "on_command", args).map_err(|err| {
+            tracing::warn!("{err}");
+            format!("/{command} failed")
+        })
+    }
+}
+
+/// Keeps the scripted plugins in sync with the files in the script directory
+struct Watcher {
+    plugins: Plugins,
+    engine: Arc<Engine>,
+    dir: PathBuf,
+    /// When each script file was last changed, as of the last check
+    seen: HashMap<String, Option<SystemTime>>,
+    /// The scripts that are registered as plugins
+    loaded: HashSet<String>,
+}
+
+/// Loads the scripts in the directory and keeps reloading them as they change
+///
+/// The scripts are read and compiled on a blocking thread, and the first load is done by the
+/// time this returns.
+pub async fn watch(plugins: Plugins, dir: PathBuf) {
+    let watcher = Watcher {
+        plugins,
+        engine: Arc::new(engine()),
+        dir,
+        seen: HashMap::new(),
+        loaded: HashSet::new(),
+    };
+    let mut watcher = watcher.reloaded().await;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            watcher = watcher.reloaded().await;
+        }
+    });
+}
+
+impl Watcher {
+    /// Reloads the scripts on a blocking thread and hands the watcher back
+    async fn reloaded(mut self) -> Self {
+        tokio::task::spawn_blocking(move || {
+            self.reload();
+            self
+        })
+        .await
+        .expect("reloading the scripts does not panic")
+    }
+
+    fn reload(&mut self) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                tracing::warn!("Failed to read {}: {err}", self.dir.display());
+                return;
+            }
+        };
+        let mut found = HashMap::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != "rhai") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let modified = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            found.insert(name.to_string(), (path.clone(), modified));
+        }
+
+        let removed: Vec<String> = self
+            .seen
+            .keys()
+            .filter(|name| !found.contains_key(*name))
+            .cloned()
+            .collect();
+        for name in removed {
+            self.seen.remove(&name);
+            if self.loaded.remove(&name) {
+                self.plugins.unregister(&name);
+            }
+        }
+
+        for (name, (path, modified)) in found {
+            if self.seen.get(&name) == Some(&modified) {
+                continue;
+            }
+            // A script that fails to load is not retried until it changes again
+            self.seen.insert(name.clone(), modified);
+            let script = match Script::load(&self.engine, &name, &path) {
+                Ok(script) => script,
+                Err(err) => {
+                    tracing::warn!("Failed to load {}: {err}", path.display());
+                    continue;
+                }
+            };
+            let script = Arc::new(script);
+            let loaded = if self.loaded.contains(&name) {
+                self.plugins.replace(script)
+            } else {
+                self.plugins.register(script)
+            };
+            if loaded {
+                self.loaded.insert(name);
+            } else {
+                tracing::warn!("Failed to load {}: {name} is taken", path.display());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, time::UNIX_EPOCH};
+
+    use super::*;
+    use crate::{clients::Clients, config::Config};
+
+    /// Returns an empty script directory that no other test uses
+    fn script_dir(test: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir =
+            std::env::temp_dir().join(format!("scripts-{test}-{}-{nanos}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a script into the directory and returns its path
+    fn write_script(dir: &Path, name: &str, source: &str) -> PathBuf {
+        let path = dir.join(format!("{name}.rhai"));
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    fn load(engine: Engine, source: &str) -> Result<Script, String> {
+        let dir = script_dir("load");
+        let path = write_script(&dir, "bot", source);
+        let script = Script::load(&Arc::new(engine), "bot", &path);
+        fs::remove_dir_all(&dir).unwrap();
+        script
+    }
+
+    fn on_message(script: &Script, context: &Context) -> Result<Dynamic, String> {
+        let args = ("alice".to_string(), "hello".to_string());
+        script.call(context, "on_message", args)
+    }
+
+    #[test]
+    fn endless_scripts_are_cut_off() {
+        let source = "fn on_message(user, text) { loop {} }";
+        let script = load(engine(), source).unwrap();
+        let started = Instant::now();
+        assert!(on_message(&script, &Context::new("lobby")).is_err());
+        assert!(started.elapsed() < TIME_LIMIT * 10);
+
+        // Either limit stops the script on its own, whichever is reached first
+        let mut few_operations = engine();
+        few_operations.set_max_operations(1_000);
+        let script = load(few_operations, source).unwrap();
+        let err = on_message(&script, &Context::new("lobby")).unwrap_err();
+        assert!(err.contains("Too many operations"), "{err}");
+
+        let mut unlimited = engine();
+        unlimited.set_max_operations(0);
+        let script = load(unlimited, source).unwrap();
+        let started = Instant::now();
+        let err = on_message(&script, &Context::new("lobby")).unwrap_err();
+        assert!(err.contains("terminated"), "{err}");
+        assert!(started.elapsed() >= TIME_LIMIT);
+        assert!(started.elapsed() < TIME_LIMIT * 10);
+    }
+
+    #[test]
+    fn scripts_cannot_say_too_much_at_once() {
+        let source = "fn on_message(user, text) { for i in 0..10 { say(text) } }";
+        let script = load(engine(), source).unwrap();
+        let context = Context::new("lobby");
+        let err = on_message(&script, &context).unwrap_err();
+        assert!(err.contains("cannot say more than"), "{err}");
+        assert_eq!(context.take_messages().len(), MAX_MESSAGES);
+    }
+
+    #[test]
+    fn scripts_cannot_take_over_built_in_commands() {
+        let err = load(engine(), r#"fn commands() { #{ join: "Join" } }"#)
+            .err()
+            .unwrap();
+        assert_eq!(err, "/join is a built-in command");
+        assert!(load(engine(), r#"fn commands() { #{ standup: "Standup" } }"#).is_ok());
+    }
+
+    #[tokio::test]
+    async fn scripts_are_reloaded_when_they_change_and_removed_with_their_file() {
+        let clients = Clients::default();
+        let plugins = Plugins::new(Arc::new(Config::default()), clients.clone()).await;
+        let dir = script_dir("reload");
+        let mut watcher = Watcher {
+            plugins: plugins.clone(),
+            engine: Arc::new(engine()),
+            dir: dir.clone(),
+            seen: HashMap::new(),
+            loaded: HashSet::new(),
+        };
+        let bot = Username::from("bot");
+        let path = write_script(&dir, "bot", r#"fn commands() { #{ first: "First" } }"#);
+        watcher.reload();
+        assert!(plugins.command("first").is_some());
+        assert!(clients.is_bot(&bot));
+
+        write_script(&dir, "bot", r#"fn commands() { #{ second: "Second" } }"#);
+        // The modification time may not have changed on coarse clocks
+        let modified = SystemTime::now() + Duration::from_secs(1);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        watcher.reload();
+        assert!(plugins.command("first").is_none());
+        assert!(plugins.command("second").is_some());
+        assert!(clients.is_bot(&bot));
+
+        fs::remove_file(&path).unwrap();
+        watcher.reload();
+        assert!(!plugins.contains("bot"));
+        assert!(!clients.is_bot(&bot));
+        fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use common::MessageId;
+use dashmap::DashMap;
+
+use crate::clients::ClientId;
+
+/// The last message that each connected user has read in a room
+///
+/// Markers are kept by client, so they follow users who change their name and are kept when
+/// users leave, so that they find their place again when they come back. Whoever connects with
+/// the name of a user who is gone starts afresh.
+#[derive(Clone, Debug, Default)]
+pub struct ReadMarkers {
+    inner: Arc<DashMap<ClientId, MessageId>>,
+}
+
+impl ReadMarkers {
+    pub fn get(&self, client: ClientId) -> Option<MessageId> {
+        self.inner.get(&client).map(|id| *id)
+    }
+
+    pub fn set(&self, client: ClientId, id: MessageId) {
+        self.inner.insert(client, id);
+    }
+
+    /// Sets the marker of the client unless it already has one
+    pub fn init(&self, client: ClientId, id: MessageId) {
+        self.inner.entry(client).or_insert(id);
+    }
+}
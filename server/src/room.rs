@@ -1,18 +1,69 @@
-use std::fmt;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
-use common::{RoomName, ServerEvent, Username};
+use common::{
+    Message, MessageId, RoomInfo, RoomName, RoomSummary, SearchQuery, ServerEvent, Username,
+};
+use dashmap::DashSet;
 use itertools::Itertools;
 use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio_util::sync::CancellationToken;
 
 use common::RoomEvent;
 
-use crate::users::Users;
+use crate::{
+    clients::{ClientId, Clients},
+    history::History,
+    read_markers::ReadMarkers,
+    sanctions::Sanctions,
+    typing::Typing,
+    users::Users,
+};
 
 #[derive(Debug, Clone)]
 pub struct Room {
     name: RoomName,
+    /// The user who created the room, `None` for rooms created by the server
+    creator: Option<Username>,
+    created_at: String,
     events: Sender<ServerEvent>,
     users: Users,
+    /// The clients of the operators of the room, the creator being the first one
+    ///
+    /// Operators, bans, mutes and invites are all keyed by client, so they follow users who
+    /// change their name and are not inherited by whoever takes a name next.
+    ops: Arc<DashSet<ClientId>>,
+    bans: Sanctions,
+    mutes: Sanctions,
+    topic: Arc<RwLock<Option<String>>>,
+    /// The message shown to users when they join the room
+    welcome: Arc<RwLock<Option<String>>>,
+    /// Whether only invited users can join the room
+    invite_only: Arc<AtomicBool>,
+    /// The key that users need to join the room
+    key: Arc<RwLock<Option<String>>>,
+    /// The clients that are invited to the room but have not joined yet
+    invites: Arc<DashSet<ClientId>>,
+    /// Whether the room is kept when it becomes empty
+    persistent: Arc<AtomicBool>,
+    /// When the last user left the room, `None` while there are users in it
+    empty_since: Arc<RwLock<Option<Instant>>>,
+    /// The most recent messages, replayed to users when they join
+    history: History,
+    /// The last message that each user has read
+    read_markers: ReadMarkers,
+    /// The users who are currently typing
+    typing: Typing,
+    /// The connected users, who are notified when a message mentions them
+    clients: Clients,
+    /// Cancelled when the room is deleted, which stops its plugins
+    closed: CancellationToken,
 }
 
 impl fmt::Display for Room {
@@ -25,13 +76,34 @@ impl Room {
     pub(crate) const ROOM_CHANNEL_CAPACITY: usize = 1024;
 
     /// Create a new room with the given name
-    pub(crate) fn new(room_name: RoomName) -> Self {
+    pub(crate) fn new(room_name: RoomName, creator: Option<Username>, clients: Clients) -> Self {
         tracing::debug!("Creating room {room_name}");
         let (events, _) = broadcast::channel(Self::ROOM_CHANNEL_CAPACITY);
+        let ops = Arc::new(DashSet::new());
+        if let Some(id) = creator.as_ref().and_then(|creator| clients.id(creator)) {
+            ops.insert(id);
+        }
         Self {
             name: room_name,
+            creator,
+            created_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
             events,
             users: Users::default(),
+            ops,
+            bans: Sanctions::default(),
+            mutes: Sanctions::default(),
+            topic: Arc::default(),
+            welcome: Arc::default(),
+            invite_only: Arc::default(),
+            key: Arc::default(),
+            invites: Arc::default(),
+            persistent: Arc::default(),
+            empty_since: Arc::default(),
+            history: History::default(),
+            read_markers: ReadMarkers::default(),
+            typing: Typing::default(),
+            clients,
+            closed: CancellationToken::new(),
         }
     }
 
@@ -40,10 +112,129 @@ impl Room {
         &self.name
     }
 
+    /// Returns the details of the room
+    pub fn info(&self) -> RoomInfo {
+        RoomInfo {
+            name: self.name.clone(),
+            topic: self.topic(),
+            creator: self.creator.clone(),
+            created_at: self.created_at.clone(),
+            welcome: self.welcome.read().unwrap().clone(),
+            users: self.user_count(),
+            invite_only: self.is_invite_only(),
+            has_key: self.key.read().unwrap().is_some(),
+            persistent: self.is_persistent(),
+        }
+    }
+
+    /// Returns the entry of the room in the room list of the given user, or of someone who is
+    /// not a user, such as the HTTP API
+    pub fn summary(&self, username: Option<&Username>) -> RoomSummary {
+        let member = username.filter(|username| self.contains(username));
+        let joined = member.is_some();
+        let (unread, mentions) = match member {
+            Some(username) => {
+                let marker = self
+                    .clients
+                    .id(username)
+                    .and_then(|client| self.read_markers.get(client));
+                self.history.unread(username, marker)
+            }
+            None => (0, 0),
+        };
+        RoomSummary {
+            name: self.name.clone(),
+            users: self.user_count(),
+            topic: self.topic(),
+            joined,
+            unread,
+            mentions,
+        }
+    }
+
+    pub fn topic(&self) -> Option<String> {
+        self.topic.read().unwrap().clone()
+    }
+
+    /// Changes the topic of the room and announces it to the users in the room
+    pub fn set_topic(&self, username: &Username, topic: Option<String>) {
+        tracing::debug!("User {username} changing the topic of room {self} to {topic:?}");
+        *self.topic.write().unwrap() = topic.clone();
+        self.send_event(username, RoomEvent::topic_change(topic.as_deref()));
+    }
+
+    pub fn set_welcome(&self, welcome: Option<String>) {
+        *self.welcome.write().unwrap() = welcome;
+    }
+
+    pub fn is_invite_only(&self) -> bool {
+        self.invite_only.load(Ordering::Relaxed)
+    }
+
+    pub fn set_invite_only(&self, invite_only: bool) {
+        self.invite_only.store(invite_only, Ordering::Relaxed);
+    }
+
+    pub fn set_key(&self, key: Option<String>) {
+        *self.key.write().unwrap() = key;
+    }
+
+    /// Returns whether the given key opens the room, which is always the case without a key
+    pub fn key_matches(&self, key: Option<&str>) -> bool {
+        self.key
+            .read()
+            .unwrap()
+            .as_deref()
+            .is_none_or(|expected| Some(expected) == key)
+    }
+
+    /// Returns whether the room is hidden from users who are not in it
+    pub fn is_private(&self) -> bool {
+        self.is_invite_only() || self.key.read().unwrap().is_some()
+    }
+
+    /// Allows the target user to join the room once, regardless of the invite-only mode or key
+    pub fn invite(&self, target: &Username) {
+        if let Some(id) = self.clients.id(target) {
+            self.invites.insert(id);
+        }
+    }
+
+    pub fn is_invited(&self, username: &Username) -> bool {
+        self.clients
+            .id(username)
+            .is_some_and(|id| self.invites.contains(&id))
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.persistent.load(Ordering::Relaxed)
+    }
+
+    pub fn set_persistent(&self, persistent: bool) {
+        self.persistent.store(persistent, Ordering::Relaxed);
+    }
+
+    /// Returns how long the room has been empty, `None` if there are users in it
+    pub fn empty_for(&self) -> Option<Duration> {
+        self.empty_since
+            .read()
+            .unwrap()
+            .map(|empty_since| empty_since.elapsed())
+    }
+
     /// Adds the specified user to the room
+    ///
+    /// Users who join for the first time start with the whole history marked as read.
     pub fn join(&self, username: &Username) -> Receiver<ServerEvent> {
         tracing::debug!("User {username} joining room {self}");
+        if let Some(id) = self.clients.id(username) {
+            self.invites.remove(&id);
+            if let Some(latest) = self.history.latest_id() {
+                self.read_markers.init(id, latest);
+            }
+        }
         self.users.insert(username);
+        *self.empty_since.write().unwrap() = None;
         let events = self.events.subscribe();
         self.send_event(username, RoomEvent::joined(&self.name));
         events
@@ -55,14 +246,50 @@ impl Room {
             "User {username} leaving room {self} with {count} users",
             count = self.users.len()
         );
-        self.users.remove(username);
+        self.remove(username);
         self.send_event(username, RoomEvent::left(&self.name));
     }
 
+    /// Takes the user out of the room, returning `false` if they were not in it
+    ///
+    /// Users lose their operator status and pending invite, while bans and mutes are kept for
+    /// when they come back.
+    fn remove(&self, username: &Username) -> bool {
+        self.stop_typing(username);
+        if let Some(id) = self.clients.id(username) {
+            self.ops.remove(&id);
+            self.invites.remove(&id);
+        }
+        let removed = self.users.remove(username);
+        if self.users.is_empty() {
+            *self.empty_since.write().unwrap() = Some(Instant::now());
+        }
+        removed
+    }
+
     pub fn list_users(&self) -> Vec<Username> {
         self.users.iter().sorted().collect()
     }
 
+    /// Subscribes to the events of the room without joining it
+    pub fn subscribe(&self) -> Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Marks the room as deleted
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+
+    /// Waits until the room is deleted
+    pub async fn closed(&self) {
+        self.closed.cancelled().await
+    }
+
+    pub fn contains(&self, username: &Username) -> bool {
+        self.users.contains(username)
+    }
+
     pub fn user_count(&self) -> usize {
         self.users.len()
     }
@@ -72,18 +299,344 @@ impl Room {
     }
 
     pub fn is_lobby(&self) -> bool {
-        self.name.as_str() == "lobby"
+        self.name == RoomName::lobby()
     }
 
     pub fn change_user_name(&self, old_name: &Username, new_name: &Username) {
         tracing::debug!("User {old_name} changing name to {new_name} in room {self}");
-        self.users.remove(old_name);
-        self.users.insert(new_name);
+        self.stop_typing(old_name);
+        self.users.rename(old_name, new_name);
         self.send_event(old_name, RoomEvent::name_change(new_name));
     }
 
-    pub fn send_message(&self, username: &Username, message: &str) {
-        self.send_event(username, RoomEvent::message(message));
+    pub fn is_op(&self, username: &Username) -> bool {
+        self.clients
+            .id(username)
+            .is_some_and(|id| self.ops.contains(&id))
+    }
+
+    pub fn is_banned(&self, username: &Username) -> bool {
+        self.clients
+            .id(username)
+            .is_some_and(|id| self.bans.contains(id))
+    }
+
+    pub fn is_muted(&self, username: &Username) -> bool {
+        self.clients
+            .id(username)
+            .is_some_and(|id| self.mutes.contains(id))
+    }
+
+    /// Makes the target user an operator of the room
+    pub fn op(&self, username: &Username, target: &Username) {
+        if let Some(id) = self.clients.id(target) {
+            self.ops.insert(id);
+            self.send_event(username, RoomEvent::op(target));
+        }
+    }
+
+    /// Removes the operator status of the target user
+    pub fn deop(&self, username: &Username, target: &Username) {
+        if let Some(id) = self.clients.id(target) {
+            self.ops.remove(&id);
+        }
+        self.send_event(username, RoomEvent::deop(target));
+    }
+
+    /// Removes the target user from the room
+    pub fn kick(&self, username: &Username, target: &Username, reason: Option<&str>) {
+        self.remove_by(username, target, RoomEvent::kick(target, reason));
+    }
+
+    /// Bans the target user for the given duration, or permanently if there is none
+    ///
+    /// The target user is removed from the room if they are in it. Returns `false` if they are
+    /// not connected, as there is no client to ban.
+    pub fn ban(&self, username: &Username, target: &Username, duration: Option<Duration>) -> bool {
+        let Some(id) = self.clients.id(target) else {
+            return false;
+        };
+        self.bans.insert(id, duration);
+        self.remove_by(username, target, RoomEvent::ban(target, duration));
+        true
+    }
+
+    /// Removes the target user from the room on behalf of a moderator and announces it
+    ///
+    /// The connection of the target user is told directly, as it could miss the room event if
+    /// it falls behind, and leaves the room when it gets the event.
+    fn remove_by(&self, username: &Username, target: &Username, event: RoomEvent) {
+        let event = ServerEvent::room_event(&self.name, username, event);
+        if self.remove(target) {
+            self.clients.send(target, event.clone());
+        }
+        let _ = self.events.send(event);
+    }
+
+    /// Lifts the ban of the target user, returning `false` if they were not banned
+    pub fn unban(&self, username: &Username, target: &Username) -> bool {
+        let unbanned = self
+            .clients
+            .id(target)
+            .is_some_and(|id| self.bans.remove(id));
+        if unbanned {
+            self.send_event(username, RoomEvent::unban(target));
+        }
+        unbanned
+    }
+
+    /// Mutes the target user for the given duration, or permanently if there is none
+    pub fn mute(&self, username: &Username, target: &Username, duration: Option<Duration>) {
+        if let Some(id) = self.clients.id(target) {
+            self.mutes.insert(id, duration);
+            self.send_event(username, RoomEvent::mute(target, duration));
+        }
+    }
+
+    /// Unmutes the target user, returning `false` if they were not muted
+    pub fn unmute(&self, username: &Username, target: &Username) -> bool {
+        let unmuted = self
+            .clients
+            .id(target)
+            .is_some_and(|id| self.mutes.remove(id));
+        if unmuted {
+            self.send_event(username, RoomEvent::unmute(target));
+        }
+        unmuted
+    }
+
+    /// Sends a message on behalf of the user and keeps it in the history
+    pub fn send_message(
+        &self,
+        username: &Username,
+        message: &str,
+    ) -> Result<MessageId, MessageError> {
+        let id = History::next_id();
+        self.post(username, Message::new(id, message))?;
+        Ok(id)
+    }
+
+    /// Sends a reply to a message that is in the history of the room
+    pub fn reply(
+        &self,
+        username: &Username,
+        reply_to: MessageId,
+        message: &str,
+    ) -> Result<MessageId, MessageError> {
+        if !self.has_message(reply_to) {
+            return Err(MessageError::NotFound(reply_to));
+        }
+        let id = History::next_id();
+        self.post(username, Message::reply(id, message, reply_to))?;
+        Ok(id)
+    }
+
+    /// Broadcasts a message and keeps it in the history, unless the user is muted
+    ///
+    /// Sending a message marks everything before it as read for the author.
+    fn post(&self, username: &Username, message: Message) -> Result<(), MessageError> {
+        if self.is_muted(username) {
+            return Err(MessageError::Muted(self.name.clone()));
+        }
+        self.stop_typing(username);
+        let client = self.clients.id(username);
+        if let Some(client) = client {
+            self.read_markers.set(client, message.id);
+        }
+        let event =
+            ServerEvent::room_event(&self.name, username, RoomEvent::Message(message.clone()));
+        self.history.push(event, client, |event| {
+            let _ = self.events.send(event);
+        });
+        self.notify_mentions(username, &message);
+        Ok(())
+    }
+
+    /// Sends the message directly to the users that it mentions or highlights
+    ///
+    /// Users who are mentioned by name are notified wherever they are, unless the room is private.
+    /// Highlight keywords only match for users in the room.
+    fn notify_mentions(&self, username: &Username, message: &Message) {
+        for (target, keyword) in self.clients.mentioned(username, message) {
+            let outsider_allowed = keyword.is_none() && !self.is_private();
+            if outsider_allowed || self.contains(&target) {
+                let mention =
+                    ServerEvent::mention(&self.name, username, message, keyword.as_deref());
+                self.clients.send(&target, mention);
+            }
+        }
+    }
+
+    /// Shows the typing indicator of the user until they stop or it times out
+    ///
+    /// Repeated signals only extend the indicator, so they are not broadcast again. Muted users
+    /// are ignored.
+    pub fn start_typing(&self, username: &Username) {
+        if self.is_muted(username) || !self.typing.start(username) {
+            return;
+        }
+        self.send_event(username, RoomEvent::typing(username));
+        let room = self.clone();
+        let username = username.clone();
+        tokio::spawn(async move {
+            while let Some(deadline) = room.typing.deadline(&username) {
+                tokio::time::sleep_until(deadline).await;
+                if room.typing.expire(&username) {
+                    room.send_event(&username, RoomEvent::typing_stopped(&username));
+                }
+            }
+        });
+    }
+
+    /// Hides the typing indicator of the user, if it is shown
+    pub fn stop_typing(&self, username: &Username) {
+        if self.typing.stop(username) {
+            self.send_event(username, RoomEvent::typing_stopped(username));
+        }
+    }
+
+    /// Adds or removes the reaction of the user to a message
+    ///
+    /// Reactions are counted per client, so only connected users can react.
+    pub fn react(
+        &self,
+        username: &Username,
+        id: MessageId,
+        emoji: &str,
+        add: bool,
+    ) -> Result<(), MessageError> {
+        if self.is_muted(username) {
+            return Err(MessageError::Muted(self.name.clone()));
+        }
+        let count = self
+            .clients
+            .id(username)
+            .and_then(|client| self.history.react(id, emoji, client, add))
+            .ok_or(MessageError::NotFound(id))?;
+        self.send_event(username, RoomEvent::reaction(id, emoji, count));
+        Ok(())
+    }
+
+    /// Returns the thread that the message belongs to, starting with its root message
+    pub fn thread(&self, id: MessageId) -> (MessageId, Vec<ServerEvent>) {
+        let root = self.history.thread_root(id);
+        (root, self.history.thread(root))
+    }
+
+    /// Returns the message events kept in the history
+    pub fn history(&self) -> Vec<ServerEvent> {
+        self.history.events()
+    }
+
+    /// Returns whether the user may read the history of the room
+    ///
+    /// Members can always read it, others only if the room is public and they are not banned.
+    pub fn can_read(&self, username: &Username) -> bool {
+        self.contains(username) || (!self.is_private() && !self.is_banned(username))
+    }
+
+    /// Returns the IDs of the messages in the history that match the query
+    pub fn search(&self, query: &SearchQuery) -> Vec<MessageId> {
+        self.history.search(query)
+    }
+
+    /// Returns a message of the history along with the messages around it
+    pub fn context(&self, id: MessageId) -> Vec<ServerEvent> {
+        self.history.context(id)
+    }
+
+    pub fn has_message(&self, id: MessageId) -> bool {
+        self.history.contains(id)
+    }
+
+    /// Marks the messages up to the given one as read, or all of them if no message is given
+    pub fn mark_read(
+        &self,
+        username: &Username,
+        id: Option<MessageId>,
+    ) -> Result<(), MessageError> {
+        let id = match id {
+            Some(id) if !self.has_message(id) => return Err(MessageError::NotFound(id)),
+            Some(id) => Some(id),
+            None => self.history.latest_id(),
+        };
+        if let Some((client, id)) = self.clients.id(username).zip(id) {
+            self.read_markers.set(client, id);
+        }
+        Ok(())
+    }
+
+    /// Changes the text of a message
+    ///
+    /// Only the author can edit a message, unless the user is allowed to moderate the room.
+    pub fn edit_message(
+        &self,
+        username: &Username,
+        id: MessageId,
+        text: &str,
+        moderator: bool,
+    ) -> Result<(), MessageError> {
+        if self.is_muted(username) {
+            return Err(MessageError::Muted(self.name.clone()));
+        }
+        self.check_author(username, id, moderator)?;
+        self.history.edit(id, text);
+        self.send_event(username, RoomEvent::edited(id, text));
+        Ok(())
+    }
+
+    /// Deletes a message
+    ///
+    /// Only the author can delete a message, unless the user is allowed to moderate the room.
+    pub fn delete_message(
+        &self,
+        username: &Username,
+        id: MessageId,
+        moderator: bool,
+    ) -> Result<(), MessageError> {
+        self.check_author(username, id, moderator)?;
+        self.history.remove(id);
+        self.send_event(username, RoomEvent::deleted(id));
+        Ok(())
+    }
+
+    /// Checks that the user sent the message, which is recognized by their connection rather
+    /// than their current name
+    fn check_author(
+        &self,
+        username: &Username,
+        id: MessageId,
+        moderator: bool,
+    ) -> Result<(), MessageError> {
+        if !self.has_message(id) {
+            return Err(MessageError::NotFound(id));
+        }
+        let is_author = self
+            .clients
+            .id(username)
+            .is_some_and(|client| self.history.is_author(id, client));
+        if !is_author && !moderator {
+            return Err(MessageError::NotAuthor(id));
+        }
+        Ok(())
+    }
+
+    pub fn send_file(
+        &self,
+        username: &Username,
+        filename: &str,
+        contents: &str,
+    ) -> Result<(), MessageError> {
+        self.send_checked(username, RoomEvent::file(filename, contents))
+    }
+
+    /// Sends an event on behalf of the user unless they are muted
+    fn send_checked(&self, username: &Username, event: RoomEvent) -> Result<(), MessageError> {
+        if self.is_muted(username) {
+            return Err(MessageError::Muted(self.name.clone()));
+        }
+        self.send_event(username, event);
+        Ok(())
     }
 
     pub fn send_event(&self, username: &Username, event: RoomEvent) {
@@ -91,3 +644,284 @@ impl Room {
         let _ = self.events.send(event);
     }
 }
+
+/// The reasons a message can be refused by a room
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageError {
+    Muted(RoomName),
+    NotFound(MessageId),
+    NotAuthor(MessageId),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MessageError::Muted(room_name) => write!(f, "You are muted in {room_name}"),
+            MessageError::NotFound(id) => write!(f, "Message {id} not found"),
+            MessageError::NotAuthor(id) => write!(f, "Message {id} was sent by someone else"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::Presence;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::clients::Client;
+
+    #[test]
+    fn authors_keep_their_messages_when_they_change_their_name() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        let renamed = Username::from("alice2");
+        clients.insert(&alice, Client::new(mpsc::unbounded_channel().0));
+        let room = Room::new("room".parse().unwrap(), None, clients.clone());
+        room.join(&alice);
+        let id = room.send_message(&alice, "hello").unwrap();
+
+        clients.rename(&alice, &renamed);
+        room.change_user_name(&alice, &renamed);
+        assert_eq!(room.edit_message(&renamed, id, "hello!", false), Ok(()));
+
+        clients.insert(&alice, Client::new(mpsc::unbounded_channel().0));
+        room.join(&alice);
+        assert_eq!(
+            room.edit_message(&alice, id, "mine now", false),
+            Err(MessageError::NotAuthor(id))
+        );
+        assert_eq!(
+            room.delete_message(&alice, id, false),
+            Err(MessageError::NotAuthor(id))
+        );
+        assert_eq!(room.delete_message(&renamed, id, false), Ok(()));
+    }
+
+    #[test]
+    fn kicked_and_banned_users_are_removed_and_told_directly() {
+        let clients = Clients::default();
+        let op = Username::from("op");
+        let target = Username::from("target");
+        let (events, mut direct) = mpsc::unbounded_channel();
+        clients.insert(&target, Client::new(events));
+        let room = Room::new("room".parse().unwrap(), Some(op.clone()), clients);
+        room.join(&op);
+
+        for remove in [
+            |room: &Room, op, target| room.kick(op, target, None),
+            |room: &Room, op, target| {
+                room.ban(op, target, None);
+            },
+        ] {
+            let _events = room.join(&target);
+            remove(&room, &op, &target);
+            assert!(!room.contains(&target));
+            assert!(room.contains(&op));
+            let event = direct.try_recv().expect("the target is told directly");
+            assert!(matches!(
+                event,
+                ServerEvent::RoomEvent {
+                    event: RoomEvent::Kick { .. } | RoomEvent::Ban { .. },
+                    ..
+                }
+            ));
+            room.unban(&op, &target);
+        }
+    }
+
+    #[test]
+    fn banned_users_stay_banned_when_they_change_their_name() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        let renamed = Username::from("alice2");
+        clients.insert(&alice, Client::new(mpsc::unbounded_channel().0));
+        let room = Room::new("room".parse().unwrap(), None, clients.clone());
+        room.join(&alice);
+        assert!(room.ban(&Username::from("op"), &alice, None));
+
+        clients.rename(&alice, &renamed);
+        assert!(room.is_banned(&renamed));
+        assert!(!room.is_banned(&alice));
+
+        clients.insert(&alice, Client::new(mpsc::unbounded_channel().0));
+        assert!(!room.is_banned(&alice));
+    }
+
+    #[test]
+    fn muted_users_stay_muted_when_they_leave_change_their_name_and_come_back() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        let renamed = Username::from("alice2");
+        clients.insert(&alice, Client::new(mpsc::unbounded_channel().0));
+        let room = Room::new("room".parse().unwrap(), None, clients.clone());
+        room.join(&alice);
+        room.mute(&Username::from("op"), &alice, None);
+
+        room.leave(&alice);
+        clients.rename(&alice, &renamed);
+        room.join(&renamed);
+        assert!(room.is_muted(&renamed));
+        assert_eq!(
+            room.send_message(&renamed, "hello"),
+            Err(MessageError::Muted(room.name().clone()))
+        );
+    }
+
+    #[test]
+    fn the_name_of_a_departed_op_does_not_make_the_next_user_op() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        clients.insert(&alice, Client::new(mpsc::unbounded_channel().0));
+        let room = Room::new(
+            "room".parse().unwrap(),
+            Some(alice.clone()),
+            clients.clone(),
+        );
+        room.join(&alice);
+        assert!(room.is_op(&alice));
+
+        room.leave(&alice);
+        clients.remove(&alice);
+        clients.insert(&alice, Client::new(mpsc::unbounded_channel().0));
+        room.join(&alice);
+        assert!(!room.is_op(&alice));
+    }
+
+    #[test]
+    fn users_cannot_react_twice_by_changing_their_name() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        let renamed = Username::from("alice2");
+        clients.insert(&alice, Client::new(mpsc::unbounded_channel().0));
+        let room = Room::new("room".parse().unwrap(), None, clients.clone());
+        room.join(&alice);
+        let id = room.send_message(&alice, "hello").unwrap();
+        let reactions = |room: &Room| match room.context(id).as_slice() {
+            [ServerEvent::RoomEvent {
+                event: RoomEvent::Message(message),
+                ..
+            }] => message.reactions.get("👍").copied(),
+            events => panic!("unexpected events: {events:?}"),
+        };
+
+        room.react(&alice, id, "👍", true).unwrap();
+        clients.rename(&alice, &renamed);
+        room.change_user_name(&alice, &renamed);
+        room.react(&renamed, id, "👍", true).unwrap();
+        assert_eq!(reactions(&room), Some(1));
+
+        room.react(&renamed, id, "👍", false).unwrap();
+        assert_eq!(reactions(&room), None);
+    }
+
+    #[test]
+    fn unread_messages_and_mentions_are_counted_per_client() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        let bob = Username::from("bob");
+        for username in [&alice, &bob] {
+            clients.insert(username, Client::new(mpsc::unbounded_channel().0));
+        }
+        let room = Room::new("room".parse().unwrap(), None, clients.clone());
+        room.join(&alice);
+        room.send_message(&alice, "before bob").unwrap();
+        room.join(&bob);
+        let counts = |username: &Username| {
+            let summary = room.summary(Some(username));
+            (summary.unread, summary.mentions)
+        };
+        assert_eq!(counts(&bob), (0, 0));
+
+        room.send_message(&alice, "hello").unwrap();
+        room.send_message(&alice, "are you there @bob?").unwrap();
+        assert_eq!(counts(&bob), (2, 1));
+        assert_eq!(counts(&alice), (0, 0));
+
+        room.mark_read(&bob, None).unwrap();
+        assert_eq!(counts(&bob), (0, 0));
+
+        // Leaving keeps the place of the user for when they come back
+        room.leave(&bob);
+        room.send_message(&alice, "bob left").unwrap();
+        room.join(&bob);
+        assert_eq!(counts(&bob), (1, 0));
+
+        // Someone else who takes the name starts with everything read
+        room.leave(&bob);
+        clients.remove(&bob);
+        clients.insert(&bob, Client::new(mpsc::unbounded_channel().0));
+        room.join(&bob);
+        assert_eq!(counts(&bob), (0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn typing_indicators_expire_without_a_new_signal() {
+        let clients = Clients::default();
+        let alice = Username::from("alice");
+        clients.insert(&alice, Client::new(mpsc::unbounded_channel().0));
+        let room = Room::new("room".parse().unwrap(), None, clients);
+        let mut events = room.join(&alice);
+        let mut next = async || match events.recv().await.unwrap() {
+            ServerEvent::RoomEvent { event, .. } => event,
+            event => panic!("unexpected event: {event:?}"),
+        };
+        assert!(matches!(next().await, RoomEvent::Joined(_)));
+
+        room.start_typing(&alice);
+        assert!(matches!(next().await, RoomEvent::Typing { .. }));
+        tokio::time::advance(Typing::TIMEOUT / 2).await;
+        room.start_typing(&alice);
+        let extended = tokio::time::Instant::now();
+        assert!(matches!(next().await, RoomEvent::TypingStopped { .. }));
+        assert_eq!(extended.elapsed(), Typing::TIMEOUT);
+
+        room.mute(&Username::from("op"), &alice, None);
+        assert!(matches!(next().await, RoomEvent::Mute { .. }));
+        room.start_typing(&alice);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn mentions_reach_users_anywhere_and_highlights_only_members() {
+        let clients = Clients::default();
+        let [author, alice, carol, dave, erin] =
+            ["author", "alice", "carol", "dave", "erin"].map(Username::from);
+        let mut direct = [&author, &alice, &carol, &dave, &erin].map(|username| {
+            let (events, direct) = mpsc::unbounded_channel();
+            clients.insert(username, Client::new(events));
+            direct
+        });
+        clients.highlight(&carol, "Deploy", true);
+        clients.highlight(&dave, "deploy", true);
+        clients.set_presence(&erin, Presence::DoNotDisturb, None);
+        let room = Room::new("backend".parse().unwrap(), None, clients.clone());
+        for username in [&author, &carol, &erin] {
+            room.join(username);
+        }
+        let mut mentions = || {
+            direct.each_mut().map(|direct| match direct.try_recv() {
+                Ok(ServerEvent::Mention { keyword, .. }) => Some(keyword),
+                _ => None,
+            })
+        };
+
+        room.send_message(&author, "@alice, @author and @erin: deploying now, DEPLOY!")
+            .unwrap();
+        assert_eq!(
+            mentions(),
+            [
+                None,
+                Some(None),
+                Some(Some("deploy".to_string())),
+                None,
+                None
+            ]
+        );
+
+        // Private rooms only notify their members
+        room.set_invite_only(true);
+        room.send_message(&author, "@alice @carol").unwrap();
+        assert_eq!(mentions(), [None, None, Some(None), None, None]);
+    }
+}
@@ -1,86 +1,486 @@
-use std::{cmp::Ordering, sync::Arc};
+use std::{cmp::Ordering, fmt, sync::Arc, time::Duration};
 
-use common::{RoomName, ServerEvent, Username};
-use dashmap::DashMap;
+use common::{RoomName, RoomSummary, SearchQuery, SearchResult, ServerEvent, Username};
+use dashmap::{mapref::entry::Entry, DashMap};
 use tokio::sync::broadcast::{Receiver, Sender};
 
-use crate::room::Room;
+use crate::{clients::Clients, config::Config, plugins::Plugins, room::Room, webhooks::Webhooks};
 
 #[derive(Clone, Debug)]
 pub struct Rooms {
     rooms: Arc<DashMap<RoomName, Room>>,
     events: Sender<ServerEvent>,
+    config: Arc<Config>,
+    clients: Clients,
+    plugins: Plugins,
+    webhooks: Webhooks,
+}
+
+/// The reasons a user can be refused to join a room
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JoinError {
+    Reserved(RoomName),
+    Banned(RoomName),
+    InviteOnly(RoomName),
+    WrongKey(RoomName),
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::Reserved(room_name) => {
+                write!(
+                    f,
+                    "{room_name} is reserved and can only be created by admins"
+                )
+            }
+            JoinError::Banned(room_name) => write!(f, "You are banned from {room_name}"),
+            JoinError::InviteOnly(room_name) => {
+                write!(
+                    f,
+                    "{room_name} is invite-only, ask an operator for an invite"
+                )
+            }
+            JoinError::WrongKey(room_name) => write!(f, "Wrong key for {room_name}"),
+        }
+    }
+}
+
+/// The reasons a search can be refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchError {
+    /// The room does not exist or the user may not read it, which look the same to them
+    NotReadable(RoomName),
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SearchError::NotReadable(room_name) => write!(f, "You cannot search {room_name}"),
+        }
+    }
 }
 
 impl Rooms {
-    pub fn new(events: Sender<ServerEvent>) -> Self {
+    /// The number of search results on a page
+    pub(crate) const SEARCH_PAGE_SIZE: usize = 10;
+
+    /// Creates the lobby and the persistent rooms from the configuration
+    pub fn new(
+        events: Sender<ServerEvent>,
+        config: Arc<Config>,
+        clients: Clients,
+        plugins: Plugins,
+        webhooks: Webhooks,
+    ) -> Self {
         let rooms = Arc::new(DashMap::new());
-        let lobby = Room::new(RoomName::lobby());
-        rooms.insert(lobby.name().clone(), lobby);
-        Self { rooms, events }
+        let persistent_rooms = config.persistent_rooms.iter().cloned();
+        for room_name in persistent_rooms.chain([RoomName::lobby()]) {
+            let room = Room::new(room_name.clone(), None, clients.clone());
+            room.set_persistent(true);
+            plugins.attach(&room);
+            webhooks.attach(&room);
+            rooms.insert(room_name, room);
+        }
+        Self {
+            rooms,
+            events,
+            config,
+            clients,
+            plugins,
+            webhooks,
+        }
     }
 
-    pub fn join(&self, username: &Username, room_name: &RoomName) -> (Room, Receiver<ServerEvent>) {
-        let room = self
-            .rooms
-            .entry(room_name.clone())
-            .or_insert_with(|| self.create_room(room_name));
+    pub fn plugins(&self) -> &Plugins {
+        &self.plugins
+    }
+
+    /// Adds the user to the room, creating the room if it does not exist
+    ///
+    /// The user is added while the map entry is locked, so a concurrent [`Rooms::leave`] can
+    /// never delete the room between it being looked up and the user joining it.
+    ///
+    /// A room that is created with a key is protected by that key from the start.
+    pub fn join(
+        &self,
+        username: &Username,
+        room_name: &RoomName,
+        key: Option<&str>,
+    ) -> Result<(Room, Receiver<ServerEvent>), JoinError> {
+        let room = match self.rooms.entry(room_name.clone()) {
+            Entry::Occupied(entry) => {
+                if !self.config.is_admin(username) {
+                    Self::check_access(entry.get(), username, key)?;
+                }
+                entry.into_ref()
+            }
+            Entry::Vacant(entry) => {
+                if self.config.is_reserved(room_name) && !self.config.is_admin(username) {
+                    return Err(JoinError::Reserved(room_name.clone()));
+                }
+                entry.insert(self.create_room(room_name, username, key))
+            }
+        };
         let events = room.join(username);
-        (room.clone(), events)
+        Ok((room.clone(), events))
     }
 
-    fn create_room(&self, room_name: &RoomName) -> Room {
+    /// Checks whether the user is allowed to enter an existing room
+    fn check_access(room: &Room, username: &Username, key: Option<&str>) -> Result<(), JoinError> {
+        if room.is_banned(username) {
+            return Err(JoinError::Banned(room.name().clone()));
+        }
+        if room.is_invited(username) {
+            return Ok(());
+        }
+        if room.is_invite_only() {
+            return Err(JoinError::InviteOnly(room.name().clone()));
+        }
+        if !room.key_matches(key) {
+            return Err(JoinError::WrongKey(room.name().clone()));
+        }
+        Ok(())
+    }
+
+    fn create_room(&self, room_name: &RoomName, creator: &Username, key: Option<&str>) -> Room {
         tracing::debug!("Creating room {room_name}");
-        let room = Room::new(room_name.clone());
-        self.send_server_event(ServerEvent::room_created(room_name));
+        let room = Room::new(
+            room_name.clone(),
+            Some(creator.clone()),
+            self.clients.clone(),
+        );
+        room.set_key(key.map(str::to_string));
+        self.plugins.attach(&room);
+        self.webhooks.attach(&room);
+        if !room.is_private() {
+            self.send_server_event(ServerEvent::room_created(room_name));
+        }
         room
     }
 
+    /// Removes the user from the room and deletes the room if it became empty
+    ///
+    /// Persistent rooms are never deleted and transient rooms are kept for the configured grace
+    /// period, in case someone comes back.
     pub fn leave(&self, username: &Username, room: &Room) {
         room.leave(username);
-        if room.is_empty() {
-            self.delete_room(room);
+        if !room.is_empty() || room.is_persistent() {
+            return;
+        }
+        match self.config.room_grace_period {
+            Some(grace_period) => {
+                let rooms = self.clone();
+                let room_name = room.name().clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(grace_period).await;
+                    rooms.delete_if_empty(&room_name, grace_period);
+                });
+            }
+            None => self.delete_if_empty(room.name(), Duration::ZERO),
         }
     }
 
-    fn delete_room(&self, room: &Room) {
-        if room.is_lobby() {
-            tracing::debug!("no users in the lobby, not deleting");
-            return;
+    /// Deletes the room if it is transient and nobody has been in it for the grace period
+    ///
+    /// The emptiness check happens while the map entry is locked, so it cannot interleave with a
+    /// [`Rooms::join`] of the same room.
+    fn delete_if_empty(&self, room_name: &RoomName, grace_period: Duration) {
+        let removed = self.rooms.remove_if(room_name, |_, room| {
+            !room.is_persistent()
+                && room.is_empty()
+                && room
+                    .empty_for()
+                    .is_some_and(|empty_for| empty_for >= grace_period)
+        });
+        if let Some((room_name, room)) = removed {
+            tracing::debug!("Deleting room {room_name}");
+            room.close();
+            if !room.is_private() {
+                self.send_server_event(ServerEvent::room_deleted(&room_name));
+            }
         }
-        tracing::debug!("Deleting room {room}");
-        self.rooms.remove(room.name());
-        self.send_server_event(ServerEvent::room_deleted(room.name()));
     }
 
-    pub fn change(
+    pub fn is_admin(&self, username: &Username) -> bool {
+        self.config.is_admin(username)
+    }
+
+    pub fn get(&self, room_name: &RoomName) -> Option<Room> {
+        self.rooms.get(room_name).map(|room| room.clone())
+    }
+
+    /// Lists the rooms that are visible to the given user
+    ///
+    /// Private rooms are only listed for the users in them.
+    pub fn list(&self, username: &Username) -> Vec<RoomSummary> {
+        sorted(
+            self.rooms
+                .iter()
+                .filter(|entry| !entry.value().is_private() || entry.value().contains(username))
+                .map(|entry| entry.value().summary(Some(username)))
+                .collect(),
+        )
+    }
+
+    /// Lists the rooms that are not private, as seen from outside of all of them
+    pub fn list_public(&self) -> Vec<RoomSummary> {
+        sorted(
+            self.rooms
+                .iter()
+                .filter(|entry| !entry.value().is_private())
+                .map(|entry| entry.value().summary(None))
+                .collect(),
+        )
+    }
+
+    /// Searches the history of the rooms that the user may read, newest messages first
+    pub fn search(
         &self,
         username: &Username,
-        previous: &Room,
-        next: &RoomName,
-    ) -> (Room, Receiver<ServerEvent>) {
-        if next == previous.name() {
-            let event = ServerEvent::error("You are already in that room");
-            self.send_server_event(event);
+        query: &SearchQuery,
+    ) -> Result<ServerEvent, SearchError> {
+        let rooms: Vec<Room> = match &query.room {
+            Some(room_name) => {
+                let room = self
+                    .rooms
+                    .get(room_name)
+                    .map(|room| room.clone())
+                    .filter(|room| room.can_read(username))
+                    .ok_or_else(|| SearchError::NotReadable(room_name.clone()))?;
+                vec![room]
+            }
+            None => self
+                .rooms
+                .iter()
+                .map(|entry| entry.value().clone())
+                .filter(|room| room.can_read(username))
+                .collect(),
+        };
+        let mut hits: Vec<_> = rooms
+            .iter()
+            .flat_map(|room| room.search(query).into_iter().map(move |id| (id, room)))
+            .collect();
+        // Message IDs grow over time, so sorting by them puts the newest messages first
+        hits.sort_by(|(a, _), (b, _)| b.cmp(a));
+        let total = hits.len();
+        let pages = total.div_ceil(Self::SEARCH_PAGE_SIZE).max(1);
+        let results = hits
+            .into_iter()
+            .skip((query.page - 1) * Self::SEARCH_PAGE_SIZE)
+            .take(Self::SEARCH_PAGE_SIZE)
+            .map(|(id, room)| SearchResult {
+                room_name: room.name().clone(),
+                id,
+                context: room.context(id),
+            })
+            .collect();
+        Ok(ServerEvent::search_results(
+            query.page, pages, total, results,
+        ))
+    }
+
+    pub fn send_server_event(&self, event: ServerEvent) {
+        let _ = self.events.send(event);
+    }
+}
+
+/// Sorts a room list by the number of users, busiest first, then by name
+fn sorted(mut list: Vec<RoomSummary>) -> Vec<RoomSummary> {
+    list.sort_by(|a, b| match b.users.cmp(&a.users) {
+        Ordering::Equal => a.name.cmp(&b.name),
+        ordering => ordering,
+    });
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use common::MessageId;
+    use tokio::sync::broadcast;
+
+    use super::*;
+
+    async fn rooms() -> Rooms {
+        rooms_with(Config::default()).await
+    }
+
+    async fn rooms_with(config: Config) -> Rooms {
+        let config = Arc::new(config);
+        let clients = Clients::default();
+        let (events, _) = broadcast::channel(16);
+        let plugins = Plugins::new(config.clone(), clients.clone()).await;
+        let webhooks = Webhooks::new(config.clone());
+        Rooms::new(events, config, clients, plugins, webhooks)
+    }
+
+    /// Races users who stay in a room and keep renaming themselves against users who keep
+    /// joining and leaving it
+    ///
+    /// After every round, the users who stayed must be in the room that the map holds, rather
+    /// than in one that was deleted under them.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn no_user_is_stranded_in_a_deleted_room() {
+        const ROUNDS: usize = 200;
+        const ITERATIONS: usize = 200;
+        const STAYING: usize = 1;
+        const LEAVING: usize = 2;
+        let rooms = rooms().await;
+        let room_name: RoomName = "stress".parse().unwrap();
+        for round in 0..ROUNDS {
+            let staying = (0..STAYING).map(|user| {
+                let rooms = rooms.clone();
+                let room_name = room_name.clone();
+                let mut username = Username::new(format!("staying{round}-{user}"));
+                let (room, _) = rooms.join(&username, &room_name, None).unwrap();
+                tokio::spawn(async move {
+                    for iteration in 0..ITERATIONS {
+                        let new_name = Username::new(format!("staying{round}-{user}-{iteration}"));
+                        room.change_user_name(&username, &new_name);
+                        username = new_name;
+                    }
+                    (username, room)
+                })
+            });
+            let staying: Vec<_> = staying.collect();
+            let leaving: Vec<_> = (0..LEAVING)
+                .map(|user| {
+                    let rooms = rooms.clone();
+                    let room_name = room_name.clone();
+                    let username = Username::new(format!("leaving{round}-{user}"));
+                    tokio::spawn(async move {
+                        for _ in 0..ITERATIONS {
+                            let (room, _) = rooms.join(&username, &room_name, None).unwrap();
+                            rooms.leave(&username, &room);
+                        }
+                    })
+                })
+                .collect();
+            for task in leaving {
+                task.await.unwrap();
+            }
+            let mut stayed = Vec::new();
+            for task in staying {
+                stayed.push(task.await.unwrap());
+            }
+
+            let room = rooms.get(&room_name).expect("the room has users");
+            for (username, _) in &stayed {
+                assert!(room.contains(username), "{username} is stranded");
+            }
+            assert_eq!(room.user_count(), STAYING);
+            for (username, room) in &stayed {
+                rooms.leave(username, room);
+            }
+            assert!(rooms.get(&room_name).is_none());
         }
-        self.leave(username, previous);
-        self.join(username, next)
     }
 
-    pub fn list(&self) -> Vec<(RoomName, usize)> {
-        let mut list: Vec<_> = self
-            .rooms
+    #[tokio::test]
+    async fn the_public_list_leaves_out_private_rooms() {
+        let rooms = rooms().await;
+        let alice = Username::from("alice");
+        let (open, _) = rooms.join(&alice, &"open".parse().unwrap(), None).unwrap();
+        let (private, _) = rooms
+            .join(&alice, &"private".parse().unwrap(), None)
+            .unwrap();
+        private.set_invite_only(true);
+        open.send_message(&alice, "hello").unwrap();
+
+        let public = rooms.list_public();
+        let names: Vec<_> = public.iter().map(|room| room.name.as_str()).collect();
+        assert!(names.contains(&"open"));
+        assert!(!names.contains(&"private"));
+        let open = public
+            .iter()
+            .find(|room| room.name.as_str() == "open")
+            .unwrap();
+        assert!(!open.joined);
+        assert_eq!(open.unread, 0);
+        assert!(rooms
+            .list(&alice)
             .iter()
-            .map(|entry| (entry.key().clone(), entry.value().user_count()))
+            .any(|room| room.name.as_str() == "private" && room.joined));
+    }
+
+    /// Returns the IDs of the results on a page of a search, along with the number of pages
+    fn search(rooms: &Rooms, username: &Username, query: &str) -> (Vec<MessageId>, usize) {
+        match rooms.search(username, &query.parse().unwrap()).unwrap() {
+            ServerEvent::SearchResults { results, pages, .. } => {
+                (results.iter().map(|result| result.id).collect(), pages)
+            }
+            event => panic!("unexpected event: {event}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn search_results_are_paginated_newest_first() {
+        let rooms = rooms().await;
+        let alice = Username::from("alice");
+        let (room, _events) = rooms.join(&alice, &RoomName::lobby(), None).unwrap();
+        let page_size = Rooms::SEARCH_PAGE_SIZE;
+        let mut ids: Vec<MessageId> = (0..page_size * 2 + 1)
+            .map(|i| room.send_message(&alice, &format!("ping {i}")).unwrap())
             .collect();
-        list.sort_by(|a, b| match b.1.cmp(&a.1) {
-            Ordering::Equal => a.0.cmp(&b.0),
-            ordering => ordering,
-        });
-        list
+        ids.reverse();
+
+        assert_eq!(
+            search(&rooms, &alice, "ping"),
+            (ids[..page_size].to_vec(), 3)
+        );
+        let page = search(&rooms, &alice, "ping page:2");
+        assert_eq!(page, (ids[page_size..page_size * 2].to_vec(), 3));
+        assert_eq!(
+            search(&rooms, &alice, "ping page:3"),
+            (ids[page_size * 2..].to_vec(), 3)
+        );
+        assert_eq!(search(&rooms, &alice, "ping page:4"), (Vec::new(), 3));
+        assert_eq!(search(&rooms, &alice, "pong"), (Vec::new(), 1));
     }
 
-    pub fn send_server_event(&self, event: ServerEvent) {
-        let _ = self.events.send(event);
+    #[tokio::test]
+    async fn private_rooms_are_only_searched_by_their_members() {
+        let rooms = rooms().await;
+        let alice = Username::from("alice");
+        let bob = Username::from("bob");
+        let secret: RoomName = "secret".parse().unwrap();
+        let (lobby, _lobby_events) = rooms.join(&alice, &RoomName::lobby(), None).unwrap();
+        let (room, _events) = rooms.join(&alice, &secret, Some("key")).unwrap();
+        let public = lobby.send_message(&alice, "launch at noon").unwrap();
+        let private = room.send_message(&alice, "launch codes").unwrap();
+
+        assert_eq!(search(&rooms, &alice, "launch").0, [private, public]);
+        assert_eq!(search(&rooms, &alice, "launch in:secret").0, [private]);
+        assert_eq!(search(&rooms, &bob, "launch").0, [public]);
+        let query = "launch in:secret".parse().unwrap();
+        assert!(matches!(
+            rooms.search(&bob, &query),
+            Err(SearchError::NotReadable(room_name)) if room_name == secret
+        ));
+    }
+
+    #[tokio::test]
+    async fn only_admins_create_reserved_rooms() {
+        let rooms = rooms_with(Config {
+            admins: HashSet::from([Username::from("root")]),
+            reserved_rooms: HashSet::from(["ops".parse().unwrap()]),
+            ..Config::default()
+        })
+        .await;
+        let alice = Username::from("alice");
+        let ops: RoomName = "#Ops".parse().unwrap();
+
+        assert!(matches!(
+            rooms.join(&alice, &ops, None),
+            Err(JoinError::Reserved(room_name)) if room_name == ops
+        ));
+        assert!(rooms.get(&ops).is_none());
+        rooms.join(&Username::from("root"), &ops, None).unwrap();
+        // Once created, the room is as open as any other
+        let (room, _) = rooms.join(&alice, &"OPS".parse().unwrap(), None).unwrap();
+        assert_eq!(room.name().as_str(), "ops");
+        assert_eq!(room.user_count(), 2);
     }
 }
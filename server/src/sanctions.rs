@@ -0,0 +1,37 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+use crate::clients::ClientId;
+
+/// Clients that are banned or muted, either permanently or until a deadline
+///
+/// Sanctions are keyed by client rather than by name, so they follow users who change their name
+/// and do not pass on to whoever takes the name of a sanctioned user.
+#[derive(Clone, Debug, Default)]
+pub struct Sanctions {
+    inner: Arc<DashMap<ClientId, Option<Instant>>>,
+}
+
+impl Sanctions {
+    /// Sanctions the client for the given duration, or permanently if there is none
+    pub fn insert(&self, client: ClientId, duration: Option<Duration>) {
+        let until = duration.map(|duration| Instant::now() + duration);
+        self.inner.insert(client, until);
+    }
+
+    pub fn remove(&self, client: ClientId) -> bool {
+        self.inner.remove(&client).is_some()
+    }
+
+    /// Returns whether the client is currently sanctioned, lifting expired sanctions
+    pub fn contains(&self, client: ClientId) -> bool {
+        self.inner.remove_if(&client, |_, until| {
+            until.is_some_and(|until| until <= Instant::now())
+        });
+        self.inner.contains_key(&client)
+    }
+}
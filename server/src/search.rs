@@ -0,0 +1,165 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use chrono::{DateTime, Local};
+use common::{MessageId, SearchQuery, Username};
+
+/// An inverted index over the messages of a room
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// The messages that contain each word
+    words: HashMap<String, BTreeSet<MessageId>>,
+    entries: HashMap<MessageId, Entry>,
+}
+
+/// An indexed message
+#[derive(Debug)]
+struct Entry {
+    author: Username,
+    sent_at: DateTime<Local>,
+    words: HashSet<String>,
+}
+
+impl SearchIndex {
+    pub fn insert(&mut self, id: MessageId, author: &Username, text: &str) {
+        let entry = Entry {
+            author: author.clone(),
+            sent_at: Local::now(),
+            words: SearchQuery::words(text).collect(),
+        };
+        for word in &entry.words {
+            self.words.entry(word.clone()).or_default().insert(id);
+        }
+        self.entries.insert(id, entry);
+    }
+
+    /// Replaces the text of an indexed message, keeping its author and date
+    pub fn update(&mut self, id: MessageId, text: &str) {
+        let Some(mut entry) = self.take(id) else {
+            return;
+        };
+        entry.words = SearchQuery::words(text).collect();
+        for word in &entry.words {
+            self.words.entry(word.clone()).or_default().insert(id);
+        }
+        self.entries.insert(id, entry);
+    }
+
+    pub fn remove(&mut self, id: MessageId) {
+        self.take(id);
+    }
+
+    /// Returns the messages that contain all words of the query and pass its filters
+    ///
+    /// The room filter is not applied here, as an index only covers a single room.
+    pub fn search(&self, query: &SearchQuery) -> Vec<MessageId> {
+        let mut postings = Vec::new();
+        for term in &query.terms {
+            let Some(ids) = self.words.get(term) else {
+                return Vec::new();
+            };
+            postings.push(ids);
+        }
+        // Start with the rarest word so that the intersection stays small
+        postings.sort_by_key(|ids| ids.len());
+        let candidates: Vec<MessageId> = match postings.split_first() {
+            Some((first, rest)) => first
+                .iter()
+                .filter(|id| rest.iter().all(|ids| ids.contains(id)))
+                .copied()
+                .collect(),
+            None => self.entries.keys().copied().collect(),
+        };
+        candidates
+            .into_iter()
+            .filter(|id| {
+                let entry = &self.entries[id];
+                query.from.as_ref().is_none_or(|from| entry.author == *from)
+                    && query
+                        .before
+                        .is_none_or(|before| entry.sent_at.date_naive() < before)
+            })
+            .collect()
+    }
+
+    /// Removes a message from the index and returns its entry
+    fn take(&mut self, id: MessageId) -> Option<Entry> {
+        let entry = self.entries.remove(&id)?;
+        for word in &entry.words {
+            if let Some(ids) = self.words.get_mut(word) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.words.remove(word);
+                }
+            }
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Days;
+
+    use super::*;
+
+    fn query(query: &str) -> SearchQuery {
+        query.parse().unwrap()
+    }
+
+    /// Searches the index and returns the IDs of the results in order
+    fn search(index: &SearchIndex, query: &str) -> Vec<u64> {
+        let mut ids: Vec<u64> = index
+            .search(&self::query(query))
+            .iter()
+            .map(MessageId::as_u64)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    fn index() -> SearchIndex {
+        let mut index = SearchIndex::default();
+        let alice = Username::from("alice");
+        let bob = Username::from("bob");
+        index.insert(MessageId::new(1), &alice, "The deploy is done.");
+        index.insert(MessageId::new(2), &bob, "Deploy failed, rolling back");
+        index.insert(MessageId::new(3), &alice, "Rolling back the deploy!");
+        index
+    }
+
+    #[test]
+    fn results_contain_every_term() {
+        let index = index();
+        assert_eq!(search(&index, "deploy"), [1, 2, 3]);
+        assert_eq!(search(&index, "DEPLOY rolling"), [2, 3]);
+        assert_eq!(search(&index, "rolling done"), Vec::<u64>::new());
+        assert_eq!(search(&index, "deploy nowhere"), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn results_pass_the_author_and_date_filters() {
+        let index = index();
+        assert_eq!(search(&index, "deploy from:alice"), [1, 3]);
+        assert_eq!(search(&index, "from:bob"), [2]);
+
+        let today = Local::now().date_naive();
+        let tomorrow = today.checked_add_days(Days::new(1)).unwrap();
+        assert_eq!(
+            search(&index, &format!("before:{today}")),
+            Vec::<u64>::new()
+        );
+        assert_eq!(search(&index, &format!("back before:{tomorrow}")), [2, 3]);
+    }
+
+    #[test]
+    fn edited_and_deleted_messages_are_reindexed() {
+        let mut index = index();
+        index.update(MessageId::new(1), "The release is done");
+        assert_eq!(search(&index, "deploy"), [2, 3]);
+        assert_eq!(search(&index, "release from:alice"), [1]);
+
+        index.remove(MessageId::new(2));
+        assert_eq!(search(&index, "deploy"), [3]);
+        assert_eq!(search(&index, "failed"), Vec::<u64>::new());
+    }
+}
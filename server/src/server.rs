@@ -1,34 +1,60 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
-use common::ServerEvent;
+use common::{CommandInfo, CommandSpec, ServerEvent};
 use tokio::{
     net::TcpListener,
     sync::broadcast::{self, Sender},
 };
 
-use crate::{connection::Connection, rooms::Rooms, users::Users};
+use crate::{
+    api, clients::Clients, config::Config, connection::Connection, plugins::Plugins, rooms::Rooms,
+    webhooks::Webhooks,
+};
 
-pub const COMMANDS: &str =
-    "/help | /name {name} | /rooms | /join {room} | /users | /nudge {name} | /quit";
+/// Returns the description of all commands, generated from the registry and the plugins
+pub fn commands_help(plugins: &Plugins) -> Vec<CommandInfo> {
+    let mut commands: Vec<_> = CommandSpec::all().iter().map(CommandSpec::info).collect();
+    commands.extend(plugins.commands_help());
+    commands
+}
 
 pub struct Server {
     listener: TcpListener,
-    users: Users,
+    clients: Clients,
     rooms: Rooms,
+    config: Arc<Config>,
     event_tx: Sender<ServerEvent>,
 }
 
 impl Server {
-    pub async fn listen(addr: SocketAddr) -> anyhow::Result<Self> {
+    pub async fn listen(addr: SocketAddr, config: Config) -> anyhow::Result<Self> {
         let listener = TcpListener::bind(addr).await?;
         let local_addr = listener.local_addr()?;
         tracing::info!("Listening on {local_addr}");
         let (event_tx, _) = broadcast::channel(1024);
+        let config = Arc::new(config);
+        let clients = Clients::default();
+        for admin in &config.admins {
+            clients.reserve(admin);
+        }
+        let plugins = Plugins::new(config.clone(), clients.clone()).await;
+        let webhooks = Webhooks::new(config.clone());
+        let rooms = Rooms::new(
+            event_tx.clone(),
+            config.clone(),
+            clients.clone(),
+            plugins,
+            webhooks,
+        );
+        if let Some(http_address) = config.http_address {
+            api::serve(http_address, rooms.clone(), clients.clone(), config.clone()).await?;
+        }
 
         Ok(Self {
             listener,
-            users: Users::default(),
-            rooms: Rooms::new(event_tx.clone()),
+            rooms,
+            clients,
+            config,
             event_tx,
         })
     }
@@ -42,10 +68,11 @@ impl Server {
                     continue;
                 }
             };
-            let users = self.users.clone();
+            let clients = self.clients.clone();
             let rooms = self.rooms.clone();
+            let config = self.config.clone();
             let events = self.event_tx.subscribe();
-            let mut connection = Connection::new(stream, events, users, rooms, addr);
+            let mut connection = Connection::new(stream, events, clients, rooms, config, addr);
             tokio::spawn(async move {
                 connection.handle().await;
             });
@@ -0,0 +1,76 @@
+use std::{sync::Arc, time::Duration};
+
+use common::Username;
+use dashmap::DashMap;
+use tokio::time::Instant;
+
+/// The users who are typing in a room, along with when their indicator expires
+#[derive(Clone, Debug, Default)]
+pub struct Typing {
+    inner: Arc<DashMap<Username, Instant>>,
+}
+
+impl Typing {
+    /// How long a typing indicator lasts without a new signal from the client
+    pub(crate) const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Starts or extends the indicator of the user, returning `true` if it was not shown yet
+    pub fn start(&self, username: &Username) -> bool {
+        let deadline = Instant::now() + Self::TIMEOUT;
+        self.inner.insert(username.clone(), deadline).is_none()
+    }
+
+    /// Removes the indicator of the user, returning `false` if there was none
+    pub fn stop(&self, username: &Username) -> bool {
+        self.inner.remove(username).is_some()
+    }
+
+    /// Returns when the indicator of the user expires
+    pub fn deadline(&self, username: &Username) -> Option<Instant> {
+        self.inner.get(username).map(|deadline| *deadline)
+    }
+
+    /// Removes the indicator of the user if it has expired, returning whether it did
+    pub fn expire(&self, username: &Username) -> bool {
+        self.inner
+            .remove_if(username, |_, deadline| *deadline <= Instant::now())
+            .is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn indicators_are_extended_until_they_expire() {
+        let typing = Typing::default();
+        let alice = Username::from("alice");
+        assert!(typing.start(&alice));
+        let first = typing.deadline(&alice).unwrap();
+
+        tokio::time::advance(Typing::TIMEOUT / 2).await;
+        assert!(!typing.start(&alice));
+        assert!(!typing.expire(&alice));
+        let extended = typing.deadline(&alice).unwrap();
+        assert_eq!(extended - first, Typing::TIMEOUT / 2);
+
+        tokio::time::advance(Typing::TIMEOUT / 2).await;
+        assert!(!typing.expire(&alice), "the first deadline was extended");
+        tokio::time::advance(Typing::TIMEOUT / 2).await;
+        assert!(typing.expire(&alice));
+        assert_eq!(typing.deadline(&alice), None);
+        assert!(!typing.stop(&alice));
+    }
+
+    #[test]
+    fn stopping_removes_the_indicator() {
+        let typing = Typing::default();
+        let alice = Username::from("alice");
+        assert!(!typing.stop(&alice));
+        typing.start(&alice);
+        assert!(typing.stop(&alice));
+        assert_eq!(typing.deadline(&alice), None);
+        assert!(typing.start(&alice));
+    }
+}
@@ -13,10 +13,30 @@ impl Users {
         self.inner.insert(username.clone())
     }
 
+    /// Replaces the old name with the new one, returning `false` if the old name is not present
+    ///
+    /// The new name is added before the old one is removed, so the set never looks empty in
+    /// between. Otherwise a room could be deleted as empty while one of its users is renamed.
+    pub fn rename(&self, old_name: &Username, new_name: &Username) -> bool {
+        if old_name == new_name {
+            return self.contains(old_name);
+        }
+        let added = self.insert(new_name);
+        let renamed = self.remove(old_name);
+        if added && !renamed {
+            self.remove(new_name);
+        }
+        renamed
+    }
+
     pub fn remove(&self, username: &Username) -> bool {
         self.inner.remove(username).is_some()
     }
 
+    pub fn contains(&self, username: &Username) -> bool {
+        self.inner.contains(username)
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = Username> + '_ {
         self.inner.iter().map(|username| username.clone())
     }
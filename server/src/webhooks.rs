@@ -0,0 +1,417 @@
+use std::{fmt, path::Path, sync::Arc, time::Duration};
+
+use common::ServerEvent;
+use hmac::{Hmac, Mac};
+use reqwest::{header::CONTENT_TYPE, StatusCode, Url};
+use sha2::Sha256;
+use tokio::{
+    fs::OpenOptions,
+    io::AsyncWriteExt,
+    sync::{
+        broadcast::error::RecvError,
+        mpsc::{self, error::TrySendError},
+    },
+};
+
+use crate::{config::Config, room::Room};
+
+/// Posts the events of rooms to the HTTP endpoints that subscribed to them
+///
+/// Every request carries the time it was signed at in `X-Webhook-Timestamp`. If a secret is
+/// configured, `X-Webhook-Signature` holds `sha256=` followed by the hex-encoded HMAC-SHA256 of
+/// the timestamp, a dot and the body.
+#[derive(Clone, Debug)]
+pub struct Webhooks {
+    client: reqwest::Client,
+    config: Arc<Config>,
+    /// How long to wait before the first retry, doubling for every retry after it
+    backoff: Duration,
+}
+
+/// The reasons an event could not be delivered
+#[derive(Debug)]
+enum DeliveryError {
+    /// The endpoint refused the event, which retrying will not change
+    Rejected(StatusCode),
+    /// The endpoint could not be reached or failed, which may be temporary
+    Failed(String),
+    /// The endpoint fell so far behind that its queue was full when the event came in
+    QueueFull,
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeliveryError::Rejected(status) => write!(f, "rejected with {status}"),
+            DeliveryError::Failed(reason) => write!(f, "{reason}"),
+            DeliveryError::QueueFull => write!(f, "too many events waiting to be delivered"),
+        }
+    }
+}
+
+impl Webhooks {
+    /// How many times an event is posted before it goes to the dead-letter log
+    const MAX_ATTEMPTS: u32 = 5;
+    const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+    /// How many events can wait for an endpoint before new ones go to the dead-letter log
+    const QUEUE_CAPACITY: usize = 1024;
+
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+            backoff: Self::INITIAL_BACKOFF,
+        }
+    }
+
+    /// Starts posting the events of a new room to the endpoints that subscribed to it
+    ///
+    /// Each endpoint gets its events in order from a queue of its own, so a slow endpoint
+    /// neither holds up the others nor makes the room drop events. The queue is bounded, and the
+    /// events that do not fit go to the dead-letter log.
+    pub fn attach(&self, room: &Room) {
+        let Some(urls) = self.config.webhooks.get(room.name()) else {
+            return;
+        };
+        for url in urls {
+            let (queue_tx, mut queue_rx) = mpsc::channel(Self::QUEUE_CAPACITY);
+            let mut events = room.subscribe();
+            let subscription = room.clone();
+            let webhooks = self.clone();
+            let queue_url = url.clone();
+            tokio::spawn(async move {
+                loop {
+                    let event = tokio::select! {
+                        event = events.recv() => event,
+                        _ = subscription.closed() => break,
+                    };
+                    match event {
+                        Ok(ServerEvent::RoomEvent { ref event, .. })
+                            if event.capability().is_some() => {}
+                        Ok(event) => {
+                            if !webhooks.enqueue(&queue_tx, &queue_url, event).await {
+                                break;
+                            }
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            tracing::warn!("Webhooks of {subscription} skipped {skipped} events");
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+            let webhooks = self.clone();
+            let url = url.clone();
+            tokio::spawn(async move {
+                while let Some(event) = queue_rx.recv().await {
+                    webhooks.deliver(&url, &event).await;
+                }
+            });
+        }
+    }
+
+    /// Queues an event for delivery, returning `false` if the delivery task is gone
+    async fn enqueue(
+        &self,
+        queue: &mpsc::Sender<ServerEvent>,
+        url: &Url,
+        event: ServerEvent,
+    ) -> bool {
+        match queue.try_send(event) {
+            Ok(()) => true,
+            Err(TrySendError::Full(event)) => {
+                tracing::warn!("Webhook {url} is too far behind, dropping an event");
+                let body = event.as_json_str();
+                self.dead_letter(url, &body, &DeliveryError::QueueFull)
+                    .await;
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Posts an event, retrying with exponential backoff and giving up to the dead-letter log
+    async fn deliver(&self, url: &Url, event: &ServerEvent) {
+        let body = event.as_json_str();
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+        let err = loop {
+            let err = match self.post(url, &body).await {
+                Ok(()) => return,
+                Err(err @ DeliveryError::Rejected(_)) => break err,
+                Err(err) => err,
+            };
+            if attempt == Self::MAX_ATTEMPTS {
+                break err;
+            }
+            tracing::debug!("Webhook {url} failed on attempt {attempt}: {err}");
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        };
+        tracing::warn!("Giving up on webhook {url} at attempt {attempt}: {err}");
+        self.dead_letter(url, &body, &err).await;
+    }
+
+    async fn post(&self, url: &Url, body: &str) -> Result<(), DeliveryError> {
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let mut request = self
+            .client
+            .post(url.clone())
+            .timeout(Self::REQUEST_TIMEOUT)
+            .header(CONTENT_TYPE, "application/json")
+            .header("X-Webhook-Timestamp", &timestamp)
+            .body(body.to_string());
+        if let Some(secret) = &self.config.webhook_secret {
+            let signature = sign(secret.expose(), &timestamp, body);
+            request = request.header("X-Webhook-Signature", format!("sha256={signature}"));
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|err| DeliveryError::Failed(err.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+            Err(DeliveryError::Rejected(status))
+        } else {
+            Err(DeliveryError::Failed(format!("failed with {status}")))
+        }
+    }
+
+    /// Keeps an event that could not be delivered, one JSON object per line
+    async fn dead_letter(&self, url: &Url, body: &str, err: &DeliveryError) {
+        let Some(path) = &self.config.webhook_dead_letters else {
+            tracing::error!("Dropped event for webhook {url}: {body}");
+            return;
+        };
+        let entry = serde_json::json!({
+            "time": chrono::Local::now().to_rfc3339(),
+            "url": url.as_str(),
+            "error": err.to_string(),
+            "event": serde_json::from_str::<serde_json::Value>(body).unwrap_or_default(),
+        });
+        if let Err(err) = append_line(path, &entry.to_string()).await {
+            tracing::error!("Failed to write to {}: {err}", path.display());
+        }
+    }
+}
+
+/// Returns the hex-encoded HMAC-SHA256 of the timestamp and the body
+fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+async fn append_line(path: &Path, line: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(format!("{line}\n").as_bytes()).await?;
+    file.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::Mutex,
+        time::{Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    use axum::{extract::State, http::HeaderMap, routing::post, Router};
+    use common::RoomName;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const BACKOFF: Duration = Duration::from_millis(10);
+
+    /// A request that reached the stub endpoint
+    struct Received {
+        headers: HeaderMap,
+        body: String,
+        at: Instant,
+    }
+
+    /// An HTTP endpoint that answers with the given statuses in turn, then with `200 OK`
+    #[derive(Clone, Default)]
+    struct Stub {
+        statuses: Arc<Mutex<Vec<StatusCode>>>,
+        received: Arc<Mutex<Vec<Received>>>,
+    }
+
+    impl Stub {
+        async fn serve(statuses: &[StatusCode]) -> (Self, Url) {
+            let stub = Stub::default();
+            stub.statuses.lock().unwrap().extend(statuses.iter().rev());
+            let app = Router::new()
+                .route("/hook", post(Stub::receive))
+                .with_state(stub.clone());
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/hook", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            (stub, url.parse().unwrap())
+        }
+
+        async fn receive(State(stub): State<Stub>, headers: HeaderMap, body: String) -> StatusCode {
+            stub.received.lock().unwrap().push(Received {
+                headers,
+                body,
+                at: Instant::now(),
+            });
+            stub.statuses
+                .lock()
+                .unwrap()
+                .pop()
+                .unwrap_or(StatusCode::OK)
+        }
+
+        fn received(&self) -> Vec<Received> {
+            std::mem::take(&mut self.received.lock().unwrap())
+        }
+    }
+
+    fn webhooks(config: Config) -> Webhooks {
+        Webhooks {
+            backoff: BACKOFF,
+            ..Webhooks::new(Arc::new(config))
+        }
+    }
+
+    /// Returns a path for a dead-letter log that no other test uses
+    fn dead_letters(test: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!(
+            "webhooks-{test}-{}-{nanos}.jsonl",
+            std::process::id()
+        ))
+    }
+
+    fn event() -> ServerEvent {
+        ServerEvent::RoomCreated(RoomName::lobby())
+    }
+
+    #[tokio::test]
+    async fn requests_are_signed() {
+        let (stub, url) = Stub::serve(&[]).await;
+        let config = Config {
+            webhook_secret: Some("hunter2".parse().unwrap()),
+            ..Config::default()
+        };
+        webhooks(config).deliver(&url, &event()).await;
+
+        let received = stub.received();
+        assert_eq!(received.len(), 1);
+        let Received { headers, body, .. } = &received[0];
+        assert_eq!(*body, event().as_json_str());
+        let timestamp = headers["X-Webhook-Timestamp"].to_str().unwrap();
+        let signature = headers["X-Webhook-Signature"].to_str().unwrap();
+        let signature = hex::decode(signature.strip_prefix("sha256=").unwrap()).unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"hunter2").unwrap();
+        mac.update(format!("{timestamp}.{body}").as_bytes());
+        assert!(mac.verify_slice(&signature).is_ok());
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_with_backoff() {
+        let statuses = [StatusCode::INTERNAL_SERVER_ERROR, StatusCode::BAD_GATEWAY];
+        let (stub, url) = Stub::serve(&statuses).await;
+        let path = dead_letters("retried");
+        let config = Config {
+            webhook_dead_letters: Some(path.clone()),
+            ..Config::default()
+        };
+        webhooks(config).deliver(&url, &event()).await;
+
+        let received = stub.received();
+        assert_eq!(received.len(), 3);
+        assert!(received[1].at - received[0].at >= BACKOFF);
+        assert!(received[2].at - received[1].at >= BACKOFF * 2);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (stub, url) = Stub::serve(&[StatusCode::BAD_REQUEST]).await;
+        let path = dead_letters("rejected");
+        let config = Config {
+            webhook_dead_letters: Some(path.clone()),
+            ..Config::default()
+        };
+        webhooks(config).deliver(&url, &event()).await;
+
+        assert_eq!(stub.received().len(), 1);
+        let dead_letters = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(dead_letters.contains("rejected with 400 Bad Request"));
+    }
+
+    #[tokio::test]
+    async fn events_that_do_not_fit_in_the_queue_go_to_the_dead_letter_log() {
+        let path = dead_letters("full");
+        let config = Config {
+            webhook_dead_letters: Some(path.clone()),
+            ..Config::default()
+        };
+        let webhooks = webhooks(config);
+        let url: Url = "http://127.0.0.1:1/hook".parse().unwrap();
+        let (queue, mut queued) = mpsc::channel(1);
+        let overflow = ServerEvent::RoomDeleted(RoomName::lobby());
+        assert!(webhooks.enqueue(&queue, &url, event()).await);
+        assert!(webhooks.enqueue(&queue, &url, overflow.clone()).await);
+
+        assert_eq!(
+            queued.try_recv().unwrap().as_json_str(),
+            event().as_json_str()
+        );
+        assert!(queued.try_recv().is_err());
+        let dead_letters = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(dead_letters.trim()).unwrap();
+        assert_eq!(entry["error"], DeliveryError::QueueFull.to_string());
+        assert_eq!(
+            entry["event"],
+            serde_json::from_str::<serde_json::Value>(&overflow.as_json_str()).unwrap()
+        );
+
+        drop(queued);
+        assert!(!webhooks.enqueue(&queue, &url, event()).await);
+    }
+
+    #[tokio::test]
+    async fn undeliverable_events_go_to_the_dead_letter_log() {
+        let statuses = [StatusCode::SERVICE_UNAVAILABLE; Webhooks::MAX_ATTEMPTS as usize];
+        let (stub, url) = Stub::serve(&statuses).await;
+        let path = dead_letters("dead");
+        let config = Config {
+            webhook_dead_letters: Some(path.clone()),
+            ..Config::default()
+        };
+        webhooks(config).deliver(&url, &event()).await;
+
+        assert_eq!(stub.received().len(), Webhooks::MAX_ATTEMPTS as usize);
+        let dead_letters = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = dead_letters.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let entry: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(entry["url"], url.as_str());
+        assert_eq!(entry["error"], "failed with 503 Service Unavailable");
+        assert_eq!(
+            entry["event"],
+            serde_json::from_str::<serde_json::Value>(&event().as_json_str()).unwrap()
+        );
+    }
+}